@@ -1,30 +1,35 @@
 use crate::{
-    mailroom::Mailroom,
+    export::ExportFormat,
+    highlight::{highlight_code, Language, TokenKind},
+    mailroom::{Mailroom, SidePanel, HISTORY_PAGE_SIZE},
+    markdown::{self, Block, Inline},
+    search::{highlight, parse_date, Query},
+    storage::{LocalStorage, NotifyLevel, SidebarSection, SortOrder},
+    util::MS_PER_DAY,
     ws::{connect, register_handler, send_message, set_close_hook, set_error_hook, set_open_hook},
 };
-use leptos::html::{Div, Input};
+use leptos::html::{Div, Input, Textarea};
 use leptos::*;
+use std::collections::HashSet;
+use std::rc::Rc;
 use std::string::ToString;
+use std::time::Duration;
 use turtle_protocol::{
-    ChannelAdded, ChannelId, ChannelsInfo, ChatMessage, CreateChannel, LoginFail, LoginSuccess,
-    SendChatMessage, UserId, UserJoined, UserLeft, UsersInfo,
+    AddReaction, ArchiveChannel, ChannelAdded, ChannelArchived, ChannelDeleted, ChannelId,
+    ChannelMembers, ChannelPins, ChannelRenamed, ChannelTopicChanged, ChannelsInfo, ChatMessage,
+    ChatMessageDeleted, ChatMessageEdited, CreateChannel, CreateGroup, DeleteChannel,
+    DeleteChatMessage, EditChatMessage, FetchChannelMembers, FetchHistory, GroupAdded, GroupId,
+    GroupsInfo, History, JoinChannel, LeaveChannel, LoginFail, LoginSuccess, Lookup, LookupResult,
+    MarkRead, MemberJoined, MemberLeft, MessagePinned, MessageUnpinned, PinMessage, Presence,
+    PresenceChanged, ProfileUpdated, ReactionAdded, ReactionRemoved, ReadMarkers, RemoveReaction,
+    RenameChannel, SendChatMessage, SendThreadReply, SendableId, SetChannelTopic, SetPresence,
+    ThreadReply, TypingStart, TypingStarted, TypingStop, TypingStopped, UnpinMessage,
+    UpdateProfile, UserId, UserJoined, UserLeft, UsersInfo,
 };
-use wasm_bindgen::prelude::wasm_bindgen;
+use wasm_bindgen::prelude::{wasm_bindgen, Closure, JsValue};
 
 #[wasm_bindgen]
 extern "C" {
-    #[wasm_bindgen(js_name = localStorage)]
-    type LocalStorage;
-
-    #[wasm_bindgen(static_method_of = LocalStorage, js_class = "localStorage", js_name = getItem)]
-    fn get_item(key: String) -> Option<String>;
-
-    #[wasm_bindgen(static_method_of = LocalStorage, js_class = "localStorage", js_name = setItem)]
-    fn set_item(key: String, value: String);
-
-    #[wasm_bindgen(static_method_of = LocalStorage, js_class = "localStorage", js_name = removeItem)]
-    fn remove_item(key: String);
-
     #[wasm_bindgen(js_name = location)]
     type Location;
 
@@ -36,24 +41,43 @@ extern "C" {
     #[wasm_bindgen(constructor)]
     fn new(ts: f64) -> Date; // todo: make ts a u64? that makes it a BigInt in JS land and makes this conversion trickier
 
+    #[wasm_bindgen(static_method_of = Date, js_name = now)]
+    fn now() -> f64;
+
     #[wasm_bindgen(method, js_name = toLocaleString)]
     fn to_locale_string(this: &Date) -> String;
 
     #[wasm_bindgen(js_name = getWsAddress)]
     fn get_ws_address() -> String;
 
+    #[wasm_bindgen(js_name = showNotification)]
+    fn show_notification(title: &str, body: &str);
+
+    /// "granted", "denied" or "default" (not asked yet)
+    #[wasm_bindgen(js_name = notificationPermission)]
+    fn notification_permission() -> String;
+
+    /// Has to come from a click, `done` gets the new permission
+    #[wasm_bindgen(js_name = requestNotificationPermission)]
+    fn request_notification_permission(done: JsValue);
+
+    /// `done` is called with whether the text made it onto the clipboard
+    #[wasm_bindgen(js_name = copyToClipboard)]
+    fn copy_to_clipboard(text: &str, done: JsValue);
+
 }
 
 #[component]
 pub fn App() -> impl IntoView {
     // create the mailroom
-    let (mailroom, set_mailroom) = create_signal(Mailroom::new(ChannelId(1)));
+    let mailroom = Mailroom::new(ChannelId(1));
     // and install it into the floorboard
     provide_context(mailroom);
-    provide_context(set_mailroom);
 
     // display main or login?
     let (display_main_view, set_display_main_view) = create_signal(false);
+    // only rebuild the browse view when it's toggled
+    let browsing = create_memo(move |_| mailroom.is_browsing());
 
     create_effect(move |_| {
         set_open_hook(|| {
@@ -70,9 +94,8 @@ pub fn App() -> impl IntoView {
 
         register_handler(move |success: LoginSuccess| {
             logging::log!("Login result: {success:?}");
-            let mailroom = mailroom.get_untracked();
             mailroom.set_current_user_id(success.id);
-            set_mailroom(mailroom);
+            mailroom.retry_lookups();
             if !display_main_view.get_untracked() {
                 set_display_main_view(true);
             }
@@ -83,6 +106,22 @@ pub fn App() -> impl IntoView {
         });
     });
 
+    // we're away whenever the window isn't focused
+    window_event_listener(ev::blur, move |_| {
+        if display_main_view.get_untracked() {
+            send_message(SetPresence {
+                presence: Presence::Away,
+            });
+        }
+    });
+    window_event_listener(ev::focus, move |_| {
+        if display_main_view.get_untracked() {
+            send_message(SetPresence {
+                presence: Presence::Online,
+            });
+        }
+    });
+
     // see if we have a saved username and password
     let maybe_username = LocalStorage::get_item("username".to_string());
     let maybe_password = LocalStorage::get_item("password".to_string());
@@ -101,6 +140,19 @@ pub fn App() -> impl IntoView {
                 <div class="p-2 h-full flex flex-col">
                     <div class="flex flex-row">
                         <h1 class="text-3xl p-2 grow font-bold text-amber-300">Turtle Chat</h1>
+                        <Search />
+                        <button class="px-2 font-bold text-xl text-amber-300 hover:underline"
+                            on:click=move |_| {
+                                mailroom.toggle_side_panel(SidePanel::Saved);
+                            }>
+                            Saved
+                        </button>
+                        <button class="px-2 font-bold text-xl text-amber-300 hover:underline"
+                            on:click=move |_| {
+                                mailroom.toggle_side_panel(SidePanel::Settings);
+                            }>
+                            Settings
+                        </button>
                         <button class="px-2 font-bold text-xl text-rose-500 hover:underline"
                             on:click=move |_| {
                                 LocalStorage::remove_item("username".to_string());
@@ -113,7 +165,12 @@ pub fn App() -> impl IntoView {
                     </div>
                     <div class="flex flex-row grow">
                         <Sidebar />
+                        {move || browsing().then(|| view! { <BrowseChannels /> })}
                         <Chat />
+                        <MemberList />
+                        <ThreadPanel />
+                        <SavedItems />
+                        <Settings />
                     </div>
                 </div>
             }
@@ -184,61 +241,361 @@ fn Login() -> impl IntoView {
     }
 }
 
+#[component]
+fn Search() -> impl IntoView {
+    let mailroom: Mailroom = expect_context();
+    let (query, set_query) = create_signal(String::new());
+    let (show_results, set_show_results) = create_signal(false);
+
+    let jump_to = move |mailbox_id: SendableId, message_id: String| {
+        mailroom.jump_to(mailbox_id, message_id);
+        set_show_results(false);
+    };
+
+    // everything the result list needs, looked up while we have the mailroom
+    let get_results = move |query: &str| {
+        mailroom
+            .search(query)
+            .into_iter()
+            .map(|(mailbox_id, chat)| {
+                let mailbox_name = mailroom.display_name(mailbox_id).unwrap_or_default();
+                let username = mailroom
+                    .user_display_name(chat.from)
+                    .unwrap_or("unknown user".to_string());
+                (mailbox_id, chat, mailbox_name, username)
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let get_panel = move || {
+        let query = query();
+        if !show_results() || Query::parse(&query).is_empty() {
+            return None;
+        }
+        let terms = Query::parse(&query).terms;
+        let results = get_results(&query);
+        let body = if results.is_empty() {
+            view! { <i class="m-1 p-1 block">"No matches"</i> }.into_view()
+        } else {
+            results
+                .into_iter()
+                .map(|(mailbox_id, chat, mailbox_name, username)| {
+                    let segments = highlight(&chat.content, &terms);
+                    let message_id = chat.id.clone();
+                    view! {
+                        <button class="m-1 p-1 w-full text-left rounded hover:bg-emerald-950"
+                            on:click=move |_| jump_to(mailbox_id, message_id.clone())>
+                            <div class="text-xs">
+                                <b>{mailbox_name}</b>
+                                " [" {username} "] "
+                                {Date::new(chat.ts).to_locale_string()}
+                            </div>
+                            <div>
+                                {segments
+                                    .into_iter()
+                                    .map(|(text, is_match)| {
+                                        if is_match {
+                                            view! {
+                                                <mark class="rounded bg-amber-300 text-emerald-950">
+                                                    {text}
+                                                </mark>
+                                            }
+                                            .into_view()
+                                        } else {
+                                            text.into_view()
+                                        }
+                                    })
+                                    .collect_view()}
+                            </div>
+                        </button>
+                    }
+                })
+                .collect_view()
+        };
+        Some(view! {
+            <div class="absolute z-10 top-full right-0 mt-1 w-[32rem] max-h-96 p-1 rounded-lg bg-emerald-900 text-amber-100 overflow-y-scroll">
+                {body}
+            </div>
+        })
+    };
+
+    view! {
+        <div class="relative mx-2 self-center">
+            <input class="p-1 w-80 rounded text-white bg-emerald-950"
+                type="search"
+                placeholder="Search... from: in: after: before:"
+                on:input=move |evt| {
+                    set_query(event_target_value(&evt));
+                    set_show_results(true);
+                }
+                on:focus=move |_| set_show_results(true)
+                on:keydown=move |evt| {
+                    if evt.key() == "Escape" {
+                        set_show_results(false);
+                    }
+                }
+                prop:value=query
+            />
+            {get_panel}
+        </div>
+    }
+}
+
+// how long to collect unknown ids before looking them up
+const LOOKUP_DELAY: Duration = Duration::from_millis(100);
+
 #[component]
 fn Sidebar() -> impl IntoView {
-    let mailroom: ReadSignal<Mailroom> = expect_context();
-    let set_mailroom: WriteSignal<Mailroom> = expect_context();
+    let mailroom: Mailroom = expect_context();
 
     create_effect(move |_| {
         logging::log!("<Sidebar/> effect running");
 
         register_handler(move |info: ChannelsInfo| {
-            let mailroom = mailroom.get_untracked();
+            let channel_ids: Vec<ChannelId> =
+                info.channels.iter().map(|channel| channel.id).collect();
             mailroom.add_channels(info);
-            set_mailroom(mailroom);
+            // membership comes with the member lists, which are only sent when asked for
+            for id in channel_ids {
+                send_message(FetchChannelMembers { id });
+            }
         });
 
         register_handler(move |channel_added: ChannelAdded| {
-            let mailroom = mailroom.get_untracked();
             let cid = channel_added.channel.id;
             mailroom.add_channel(channel_added.channel);
             if mailroom.current_user_id() == Some(channel_added.created_by) {
+                mailroom.mark_joined(cid);
                 mailroom.set_active(cid);
+            } else {
+                send_message(FetchChannelMembers { id: cid });
+            }
+        });
+
+        register_handler(move |info: GroupsInfo| {
+            mailroom.add_groups(info);
+        });
+
+        register_handler(move |group_added: GroupAdded| {
+            let gid = group_added.group.id;
+            mailroom.add_group(group_added.group);
+            if mailroom.current_user_id() == Some(group_added.created_by) {
+                mailroom.set_active(gid);
             }
-            set_mailroom(mailroom);
+        });
+
+        register_handler(move |renamed: ChannelRenamed| {
+            mailroom.rename_channel(renamed);
+        });
+
+        register_handler(move |archived: ChannelArchived| {
+            mailroom.archive_channel(archived);
+        });
+
+        register_handler(move |deleted: ChannelDeleted| {
+            mailroom.remove_channel(deleted);
+        });
+
+        register_handler(move |read_markers: ReadMarkers| {
+            mailroom.apply_read_markers(read_markers);
+        });
+
+        register_handler(move |changed: ChannelTopicChanged| {
+            mailroom.change_topic(changed);
+        });
+
+        register_handler(move |members: ChannelMembers| {
+            mailroom.set_channel_members(members);
+        });
+
+        register_handler(move |joined: MemberJoined| {
+            mailroom.add_channel_member(joined);
+        });
+
+        register_handler(move |left: MemberLeft| {
+            mailroom.remove_channel_member(left);
         });
 
         register_handler(move |users_info: UsersInfo| {
-            let mailroom = mailroom.get_untracked();
             mailroom.add_users(users_info);
-            set_mailroom(mailroom);
         });
 
         register_handler(move |user_joined: UserJoined| {
-            let mailroom = mailroom.get_untracked();
+            let user_id = user_joined.user.id;
             mailroom.add_user(user_joined.user);
-            set_mailroom(mailroom);
+            // they're here now, even if they'd left before
+            mailroom.set_presence(user_id, Presence::Online);
         });
 
         register_handler(move |user_left: UserLeft| {
-            let mailroom = mailroom.get_untracked();
-            mailroom.remove_user(user_left.id);
-            set_mailroom(mailroom);
+            mailroom.set_presence(user_left.id, Presence::Offline);
         });
+
+        register_handler(move |changed: PresenceChanged| {
+            mailroom.update_presence(changed);
+        });
+
+        register_handler(move |updated: ProfileUpdated| {
+            mailroom.update_profile(updated);
+        });
+
+        register_handler(move |result: LookupResult| {
+            mailroom.resolve(result);
+        });
+    });
+
+    // unknown ids turn up in bursts, e.g. a page of history, so ask about them together
+    let lookup_pending = store_value(false);
+    create_effect(move |_| {
+        if mailroom.has_unresolved() && !lookup_pending.get_value() {
+            lookup_pending.set_value(true);
+            set_timeout(
+                move || {
+                    lookup_pending.set_value(false);
+                    let unresolved = mailroom.take_unresolved();
+                    if !unresolved.is_empty() {
+                        send_message(Lookup {
+                            users: unresolved.users.into_iter().collect(),
+                            channels: unresolved.channels.into_iter().collect(),
+                            groups: unresolved.groups.into_iter().collect(),
+                        });
+                    }
+                },
+                LOOKUP_DELAY,
+            );
+        }
     });
 
     let (show_channel_add, set_show_channel_add) = create_signal(false);
     let (new_channel_name, set_new_channel_name) = create_signal("".to_string());
     let new_channel_ref: NodeRef<Input> = create_node_ref();
 
-    let get_channel_list = move || {
-        let mailroom = mailroom();
-        mailroom.channel_list()
+    let get_channel_list = move || mailroom.channel_list();
+
+    let get_user_list = move || mailroom.user_list();
+
+    let get_group_list = move || mailroom.group_list();
+    let (show_group_picker, set_show_group_picker) = create_signal(false);
+
+    let get_favorite_list = move || mailroom.favorite_list();
+
+    // what's being dragged around while the sort order is manual
+    let dragging = create_rw_signal(None::<SendableId>);
+    let visible_channels = Signal::derive(move || {
+        get_channel_list()
+            .into_iter()
+            .map(|(cid, _)| cid.into())
+            .collect::<Vec<SendableId>>()
+    });
+    let visible_users = Signal::derive(move || {
+        get_user_list()
+            .into_iter()
+            .map(|(uid, _)| uid.into())
+            .collect::<Vec<SendableId>>()
+    });
+    let visible_groups = Signal::derive(move || {
+        get_group_list()
+            .into_iter()
+            .map(|(gid, _)| gid.into())
+            .collect::<Vec<SendableId>>()
+    });
+    let visible_favorites = Signal::derive(move || {
+        get_favorite_list()
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect::<Vec<SendableId>>()
+    });
+
+    let favorites_section = move || {
+        if get_favorite_list().is_empty() {
+            return None;
+        }
+        Some(view! {
+            <h2 class="font-bold text-lg mx-3 mt-2">Favorites</h2>
+            <div class="max-h-40 mx-2 p-1 bg-emerald-900 rounded-lg overflow-y-scroll">
+                <For
+                    each=get_favorite_list
+                    key=|(id, name)| (*id, name.clone())
+                    children=move |(id, name): (SendableId, String)| {
+                        let entry = match id {
+                            SendableId::C(cid) => view! {
+                                <DisplayChannel channel_id=cid display_name=name />
+                            }
+                            .into_view(),
+                            SendableId::U(uid) => view! {
+                                <DisplayUser user_id=uid username=name />
+                            }
+                            .into_view(),
+                            SendableId::G(gid) => view! {
+                                <DisplayGroup group_id=gid />
+                            }
+                            .into_view(),
+                        };
+                        view! {
+                            <SortableEntry id=id section=SidebarSection::Favorites dragging=dragging visible=visible_favorites>
+                                {entry}
+                            </SortableEntry>
+                        }
+                    }
+                />
+            </div>
+        })
+    };
+
+    let get_sort_select = move || {
+        let sort_order = mailroom.sort_order();
+        view! {
+            <select class="mr-3.5 mt-2 h-6 text-sm rounded text-amber-300 bg-emerald-900"
+                title="Sort order"
+                on:change=move |evt| {
+                    let label = event_target_value(&evt);
+                    if let Some(order) = SortOrder::ALL.into_iter().find(|o| o.label() == label) {
+                        mailroom.set_sort_order(order);
+                    }
+                }>
+                {SortOrder::ALL
+                    .into_iter()
+                    .map(|option| {
+                        view! {
+                            <option value=option.label() selected=option == sort_order>
+                                {option.label()}
+                            </option>
+                        }
+                    })
+                    .collect_view()}
+            </select>
+        }
     };
 
-    let get_user_list = move || {
-        let mailroom = mailroom();
-        mailroom.user_list()
+    let (show_archived, set_show_archived) = create_signal(false);
+    let get_archived_list = move || mailroom.archived_channel_list();
+
+    let archived_section = move || {
+        let archived_count = get_archived_list().len();
+        if archived_count == 0 {
+            return None;
+        }
+        Some(view! {
+            <div class="mx-2 mt-1 flex flex-col">
+                <button class="text-sm text-left hover:underline"
+                    on:click=move |_| set_show_archived(!show_archived())>
+                    {move || if show_archived() { "▾" } else { "▸" }}
+                    " Archived (" {archived_count} ")"
+                </button>
+                <Show when=show_archived>
+                    <div class="max-h-32 p-1 bg-emerald-900 rounded-lg opacity-75 overflow-y-scroll">
+                        <For
+                            each=get_archived_list
+                            key=|(cid, name)| (*cid, name.clone())
+                            let:child>
+                            <DisplayChannel
+                                channel_id=child.0
+                                display_name=child.1 />
+                        </For>
+                    </div>
+                </Show>
+            </div>
+        })
     };
 
     let add_channel_form = move || {
@@ -275,7 +632,8 @@ fn Sidebar() -> impl IntoView {
 
     view! {
         <div class="basis-1/4 h-full text-amber-300 bg-green-950 rounded-l-md flex flex-col">
-            <div class="h-1/2 flex flex-col">
+            {favorites_section}
+            <div class="h-1/2 grow flex flex-col">
                 <div class="flex flex-row">
                     <h2 class="font-bold text-lg mx-3 my-2 grow">
                         Channels
@@ -299,29 +657,59 @@ fn Sidebar() -> impl IntoView {
                         }>
                         { move || if show_channel_add() { "X" } else { "+" } }
                     </button>
+                    <button class="text-sm mr-3.5 pt-1 hover:underline"
+                        on:click=move |_| {
+                            mailroom.set_browsing(!mailroom.is_browsing());
+                        }>
+                        browse
+                    </button>
+                    {get_sort_select}
                 </div>
                 {add_channel_form}
                 <div class="bg-emerald-900 grow mx-2 p-1 rounded-lg overflow-y-scroll">
                     <For
-                        each=get_channel_list
-                        key=|(cid, _)| *cid
-                        let:child>
-                        <DisplayChannel
-                            channel_id=child.0
-                            display_name=child.1 />
+                        each=move || mailroom.category_list()
+                        key=|category| category.clone()
+                        let:category>
+                        <ChannelCategory
+                            category=category
+                            dragging=dragging
+                            visible=visible_channels />
                     </For>
                 </div>
+                {archived_section}
             </div>
-            <div class="h-1/2 flex flex-col">
-                <h2 class="font-bold text-lg mx-2 pt-2 pl-2">Users</h2>
+            <div class="h-1/2 grow flex flex-col">
+                <div class="flex flex-row">
+                    <h2 class="font-bold text-lg mx-2 pt-2 pl-2 grow">Users</h2>
+                    <button class="text-sm mr-3.5 pt-2 hover:underline"
+                        on:click=move |_| {
+                            set_show_group_picker(!show_group_picker());
+                        }>
+                        { move || if show_group_picker() { "cancel" } else { "+ group" } }
+                    </button>
+                </div>
+                <Show when=show_group_picker>
+                    <GroupPicker on_done=move |_| set_show_group_picker(false) />
+                </Show>
                 <div class="grow bg-emerald-900 m-2 p-1 rounded-lg overflow-y-scroll">
+                    <For
+                        each=get_group_list
+                        key=|(gid, name)| (*gid, name.clone())
+                        let:child>
+                        <SortableEntry id=child.0.into() section=SidebarSection::Groups dragging=dragging visible=visible_groups>
+                            <DisplayGroup group_id=child.0 />
+                        </SortableEntry>
+                    </For>
                     <For
                         each=get_user_list
                         key=|(uid, _)| *uid
                         let:child>
-                        <DisplayUser
-                            user_id=child.0
-                            username=child.1 />
+                        <SortableEntry id=child.0.into() section=SidebarSection::Users dragging=dragging visible=visible_users>
+                            <DisplayUser
+                                user_id=child.0
+                                username=child.1 />
+                        </SortableEntry>
                     </For>
                 </div>
             </div>
@@ -329,13 +717,138 @@ fn Sidebar() -> impl IntoView {
     }
 }
 
+/// Lets sidebar entries be dragged into place when the sort order is manual
+#[component]
+fn SortableEntry(
+    id: SendableId,
+    section: SidebarSection,
+    dragging: RwSignal<Option<SendableId>>,
+    // the list the entry is in, as currently shown
+    visible: Signal<Vec<SendableId>>,
+    children: Children,
+) -> impl IntoView {
+    let mailroom: Mailroom = expect_context();
+
+    let is_manual = move || mailroom.sort_order() == SortOrder::Manual;
+    // only reorder within the same list
+    let can_drop_here = move || {
+        dragging
+            .get_untracked()
+            .filter(|dragged| *dragged != id && visible.with_untracked(|ids| ids.contains(dragged)))
+    };
+
+    view! {
+        <div draggable=move || if is_manual() { "true" } else { "false" }
+            on:dragstart=move |evt| {
+                // firefox won't start a drag without some data
+                if let Some(data) = evt.data_transfer() {
+                    let _ = data.set_data("text/plain", "");
+                }
+                dragging.set(Some(id));
+            }
+            on:dragover=move |evt| {
+                if can_drop_here().is_some() {
+                    evt.prevent_default();
+                }
+            }
+            on:drop=move |evt| {
+                evt.prevent_default();
+                if let Some(dragged) = can_drop_here() {
+                    mailroom.move_mailbox(section, dragged, id, &visible.get_untracked());
+                }
+                dragging.set(None);
+            }
+            on:dragend=move |_| dragging.set(None)>
+            {children()}
+        </div>
+    }
+}
+
+/// A named, foldable group of channels, uncategorized channels go without a header
+#[component]
+fn ChannelCategory(
+    category: Option<String>,
+    dragging: RwSignal<Option<SendableId>>,
+    visible: Signal<Vec<SendableId>>,
+) -> impl IntoView {
+    let mailroom: Mailroom = expect_context();
+    let category = store_value(category);
+
+    let get_channels = move || category.with_value(|category| mailroom.category_channels(category));
+    let is_collapsed = move || {
+        category.with_value(|category| {
+            category
+                .as_deref()
+                .map(|name| mailroom.is_collapsed(name))
+                .unwrap_or(false)
+        })
+    };
+    // folded categories still say what's waiting inside
+    let get_badge = move || {
+        if !is_collapsed() {
+            return None;
+        }
+        let (unread, mentioned) =
+            category.with_value(|category| mailroom.category_unread(category));
+        (unread > 0).then(|| {
+            let css_class = if mentioned {
+                "px-1.5 rounded-full bg-rose-700 text-white"
+            } else {
+                "px-1.5 rounded-full bg-amber-300 text-green-900"
+            };
+            view! { <span class=css_class>{unread}</span> }
+        })
+    };
+
+    let header = category.get_value().map(|name| {
+        let toggle_name = name.clone();
+        view! {
+            <button class="w-full flex flex-row mt-1 px-1 text-sm text-left font-bold uppercase text-amber-300 hover:underline"
+                on:click=move |_| mailroom.toggle_collapsed(&toggle_name)>
+                <span class="grow">
+                    {move || if is_collapsed() { "▸" } else { "▾" }}
+                    " " {name}
+                </span>
+                {get_badge}
+            </button>
+        }
+    });
+
+    view! {
+        {header}
+        <Show when=move || !is_collapsed()>
+            <For
+                each=get_channels
+                key=|(cid, name)| (*cid, name.clone())
+                let:child>
+                <SortableEntry id=child.0.into() section=SidebarSection::Channels dragging=dragging visible=visible>
+                    <DisplayChannel
+                        channel_id=child.0
+                        display_name=child.1 />
+                </SortableEntry>
+            </For>
+        </Show>
+    }
+}
+
 #[component]
 fn DisplayChannel(channel_id: ChannelId, display_name: String) -> impl IntoView {
-    let mailroom: ReadSignal<Mailroom> = expect_context();
-    let set_mailroom: WriteSignal<Mailroom> = expect_context();
+    let mailroom: Mailroom = expect_context();
+    let display_name = store_value(display_name);
+
+    let (renaming, set_renaming) = create_signal(false);
+    let (new_name, set_new_name) = create_signal(display_name.get_value());
+    let rename_ref: NodeRef<Input> = create_node_ref();
+
+    create_effect(move |_| {
+        if renaming() {
+            if let Some(input) = rename_ref() {
+                let _ = input.focus();
+            }
+        }
+    });
 
     let get_css_class = move || {
-        let mailroom = mailroom();
         if mailroom.is_active(channel_id) {
             "m-1 p-1 block rounded bg-emerald-700 text-neutral-950 font-medium"
         } else if mailroom.has_unread(channel_id) {
@@ -345,27 +858,118 @@ fn DisplayChannel(channel_id: ChannelId, display_name: String) -> impl IntoView
         }
     };
 
+    let rename_form = move || {
+        view! {
+            <form class="flex flex-row m-1"
+                on:submit=move |evt| {
+                    evt.prevent_default();
+                    let name = new_name();
+                    if name.len() > 0 && name != display_name.get_value() {
+                        send_message(RenameChannel {
+                            id: channel_id,
+                            name,
+                        });
+                    }
+                    set_renaming(false);
+                }
+            >
+                <input class="p-1 grow rounded text-white bg-emerald-950" type="text"
+                    on:input=move |evt| {
+                        // same rules as creating a channel, no spaces
+                        set_new_name(event_target_value(&evt).trim().to_string());
+                    }
+                    on:keydown=move |evt| {
+                        if evt.key() == "Escape" {
+                            set_renaming(false);
+                        }
+                    }
+                    prop:value=new_name
+                    node_ref=rename_ref
+                />
+            </form>
+        }
+    };
+
+    let channel_link = move || {
+        let archive_action = (!mailroom.is_archived(channel_id)).then(|| {
+            view! {
+                <button class="px-1 hover:text-white" title="Archive"
+                    on:click=move |_| {
+                        let confirmed = window()
+                            .confirm_with_message("Archive this channel? It will become read-only.")
+                            .unwrap_or(false);
+                        if confirmed {
+                            send_message(ArchiveChannel { id: channel_id });
+                        }
+                    }>
+                    "🗄"
+                </button>
+            }
+        });
+        view! {
+            <a  class={get_css_class}
+                class:opacity-50=move || mailroom.is_muted(channel_id)
+                href={format!("#{}", display_name.get_value())}
+                on:click=move |evt| {
+                    evt.prevent_default(); // todo: history api?
+                    mailroom.set_active(channel_id);
+                }>
+                "#" {display_name.get_value()}
+                <DraftMarker id=channel_id.into() />
+            </a>
+            <div class="absolute right-2 top-1.5 invisible group-hover:visible text-sm">
+                <StarButton id=channel_id.into() />
+                <button class="px-1 hover:text-white" title="Set category"
+                    on:click=move |_| {
+                        let current = mailroom.channel_category(channel_id).unwrap_or_default();
+                        let maybe_category = window()
+                            .prompt_with_message_and_default(
+                                "Category, only you will see it (leave blank to use the server's)",
+                                &current,
+                            )
+                            .ok()
+                            .flatten();
+                        if let Some(category) = maybe_category {
+                            mailroom.set_channel_category(channel_id, &category);
+                        }
+                    }>
+                    "📁"
+                </button>
+                <button class="px-1 hover:text-white" title="Rename"
+                    on:click=move |_| {
+                        set_new_name(display_name.get_value());
+                        set_renaming(true);
+                    }>
+                    "✎"
+                </button>
+                {archive_action}
+                <button class="px-1 hover:text-white" title="Delete"
+                    on:click=move |_| {
+                        let confirmed = window()
+                            .confirm_with_message("Delete this channel and all of its messages?")
+                            .unwrap_or(false);
+                        if confirmed {
+                            send_message(DeleteChannel { id: channel_id });
+                        }
+                    }>
+                    "🗑"
+                </button>
+            </div>
+        }
+    };
+
     view! {
-        <a  class={get_css_class}
-            href={format!("#{}", display_name.clone())}
-            on:click=move |evt| {
-                evt.prevent_default(); // todo: history api?
-                let mailroom = mailroom();
-                mailroom.set_active(channel_id);
-                set_mailroom(mailroom);
-            }>
-            #{display_name}
-        </a>
+        <div class="group relative">
+            {move || if renaming() { rename_form().into_view() } else { channel_link().into_view() }}
+        </div>
     }
 }
 
 #[component]
 fn DisplayUser(user_id: UserId, username: String) -> impl IntoView {
-    let mailroom: ReadSignal<Mailroom> = expect_context();
-    let set_mailroom: WriteSignal<Mailroom> = expect_context();
+    let mailroom: Mailroom = expect_context();
 
     let get_css_class = move || {
-        let mailroom = mailroom();
         if mailroom.is_active(user_id) {
             "m-1 p-1 block rounded bg-emerald-700 text-white font-medium"
         } else if mailroom.has_unread(user_id) {
@@ -376,83 +980,750 @@ fn DisplayUser(user_id: UserId, username: String) -> impl IntoView {
     };
     let display_username = username.clone();
 
+    // nicknames can change under us, the list only passes the name it was sorted by
     let get_display_name = move || {
-        let mailroom = mailroom();
+        let name = mailroom
+            .user_display_name(user_id)
+            .unwrap_or(display_username.clone());
         match mailroom.current_user_id() {
-            Some(id) if id == user_id => format!("{} (you)", display_username.clone()),
-            _ => display_username.clone(),
+            Some(id) if id == user_id => format!("{name} (you)"),
+            _ => name,
         }
     };
+    // the real username, when another name is hiding it
+    let get_real_username = move || mailroom.hidden_username(user_id);
+
+    let is_ignored = move || mailroom.is_ignored(user_id);
+    // can't ignore or nickname yourself
+    let other_user_actions = move || {
+        (mailroom.current_user_id() != Some(user_id)).then(|| {
+            view! {
+                <button class="px-1 hover:text-white" title="Set nickname"
+                    on:click=move |_| {
+                        let current = mailroom.nickname(user_id).unwrap_or_default();
+                        let maybe_nickname = window()
+                            .prompt_with_message_and_default(
+                                "Nickname, only you will see it (leave blank to clear)",
+                                &current,
+                            )
+                            .ok()
+                            .flatten();
+                        if let Some(nickname) = maybe_nickname {
+                            mailroom.set_nickname(user_id, &nickname);
+                        }
+                    }>
+                    "✎"
+                </button>
+                <button class="px-1 hover:text-white"
+                    title=move || if is_ignored() { "Unignore" } else { "Ignore" }
+                    on:click=move |_| {
+                        mailroom.toggle_ignored(user_id);
+                    }>
+                    "🚫"
+                </button>
+            }
+        })
+    };
+
+    let get_presence_dot = move || {
+        let (css_class, label) = match mailroom.presence(user_id) {
+            Presence::Online => (
+                "inline-block w-2 h-2 mr-2 rounded-full bg-green-400",
+                "online",
+            ),
+            Presence::Away => (
+                "inline-block w-2 h-2 mr-2 rounded-full bg-amber-400",
+                "away",
+            ),
+            Presence::Offline => (
+                "inline-block w-2 h-2 mr-2 rounded-full border border-amber-100",
+                "offline",
+            ),
+        };
+        view! { <span class=css_class title=label></span> }
+    };
 
     view! {
-        <a class={get_css_class}
-            href={format!("@{}", username.clone())}
-            on:click=move |evt| {
-                evt.prevent_default();
-                let mailroom = mailroom();
-                mailroom.set_active(user_id);
-                set_mailroom(mailroom);
-            }>
-            {get_display_name}
-        </a>
+        <div class="group relative">
+            <a class={get_css_class}
+                class:opacity-50=move || mailroom.is_muted(user_id) || is_ignored()
+                title=get_real_username
+                href={format!("@{}", username.clone())}
+                on:click=move |evt| {
+                    evt.prevent_default();
+                    mailroom.set_active(user_id);
+                }>
+                {get_presence_dot}
+                {get_display_name}
+                <DraftMarker id=user_id.into() />
+            </a>
+            <div class="absolute right-2 top-1.5 invisible group-hover:visible text-sm">
+                <StarButton id=user_id.into() />
+                {other_user_actions}
+            </div>
+        </div>
     }
 }
 
 #[component]
-fn Chat() -> impl IntoView {
-    let mailroom: ReadSignal<Mailroom> = expect_context();
-    let set_mailroom: WriteSignal<Mailroom> = expect_context();
-
-    create_effect(move |_| {
-        register_handler(move |chat_msg: ChatMessage| {
-            let mailroom = mailroom.get_untracked();
-            mailroom.add_message(chat_msg);
-            set_mailroom(mailroom);
-        });
-    });
+fn DisplayGroup(group_id: GroupId) -> impl IntoView {
+    let mailroom: Mailroom = expect_context();
 
-    let active_messages = move || {
-        let mailroom = mailroom();
-        mailroom.active_messages()
+    let get_css_class = move || {
+        if mailroom.is_active(group_id) {
+            "m-1 p-1 block rounded bg-emerald-700 text-white font-medium"
+        } else if mailroom.has_unread(group_id) {
+            "m-1 p-1 block rounded hover:bg-green-500 text-amber-100 font-bold bg-rose-700"
+        } else {
+            "m-1 p-1 block rounded hover:bg-emerald-950 text-amber-100"
+        }
     };
 
-    let get_chat_title = move || {
-        let mailroom = mailroom();
-        mailroom.active_display_name()
+    view! {
+        <div class="group relative">
+            <a class={get_css_class}
+                class:opacity-50=move || mailroom.is_muted(group_id)
+                href=format!("&{}", group_id.0)
+                on:click=move |evt| {
+                    evt.prevent_default();
+                    mailroom.set_active(group_id);
+                }>
+                {move || mailroom.group_name(group_id)}
+                <DraftMarker id=group_id.into() />
+            </a>
+            <div class="absolute right-2 top-1.5 invisible group-hover:visible text-sm">
+                <StarButton id=group_id.into() />
+            </div>
+        </div>
+    }
+}
+
+/// Picks people to message, one person opens the DM and more start (or reopen) a group
+#[component]
+fn GroupPicker(#[prop(into)] on_done: Callback<()>) -> impl IntoView {
+    let mailroom: Mailroom = expect_context();
+
+    let selected = create_rw_signal(HashSet::<UserId>::new());
+    let selected_count = move || selected.with(|selected| selected.len());
+
+    let start = move |_| {
+        let others = selected.get_untracked();
+        match others.len() {
+            0 => return,
+            1 => {
+                if let Some(uid) = others.into_iter().next() {
+                    mailroom.set_active(uid);
+                }
+            }
+            _ => match mailroom.find_group(&others) {
+                Some(gid) => mailroom.set_active(gid),
+                None => send_message(CreateGroup {
+                    members: others.into_iter().collect(),
+                }),
+            },
+        }
+        selected.set(HashSet::new());
+        on_done.call(());
     };
 
     view! {
-        <div class="basis-3/4 overflow-hidden flex flex-col bg-green-950 rounded-r-md">
-            <h1 class="mx-2 my-1 text-xl font-bold text-amber-300">{get_chat_title}</h1>
-            <DisplayMessages messages={active_messages} />
-            <ChatInput />
+        <div class="mx-2 p-1 bg-emerald-900 rounded-lg flex flex-col">
+            <div class="max-h-40 overflow-y-scroll">
+                <For
+                    each=move || mailroom.other_users()
+                    key=|(uid, name)| (*uid, name.clone())
+                    let:child>
+                    <label class="m-1 p-1 flex flex-row items-center rounded hover:bg-emerald-950 text-amber-100">
+                        <input class="mr-2" type="checkbox"
+                            prop:checked=move || selected.with(|selected| selected.contains(&child.0))
+                            on:change=move |_| {
+                                selected.update(|selected| {
+                                    if !selected.remove(&child.0) {
+                                        selected.insert(child.0);
+                                    }
+                                });
+                            }
+                        />
+                        {child.1}
+                    </label>
+                </For>
+            </div>
+            <button class="m-1 bg-amber-500 hover:bg-emerald-700 disabled:opacity-50 text-white p-1 rounded"
+                prop:disabled=move || selected_count() == 0
+                on:click=start>
+                {move || match selected_count() {
+                    0 | 1 => "Message".to_string(),
+                    n => format!("Start group with {n}"),
+                }}
+            </button>
         </div>
     }
 }
 
+/// Flags conversations with an unsent message, the open one doesn't need it
 #[component]
-fn DisplayMessages<F: Fn() -> Vec<ChatMessage> + Copy + 'static>(messages: F) -> impl IntoView {
-    let (scrolled_bottom, set_scrolled_bottom) = create_signal(true);
-    let messages_element: NodeRef<Div> = create_node_ref();
+fn DraftMarker(id: SendableId) -> impl IntoView {
+    let mailroom: Mailroom = expect_context();
+
+    move || {
+        (mailroom.has_draft(id) && !mailroom.is_active(id)).then(|| {
+            view! { <span class="ml-2 text-xs italic font-normal text-amber-300">"draft"</span> }
+        })
+    }
+}
+
+#[component]
+fn StarButton(id: SendableId) -> impl IntoView {
+    let mailroom: Mailroom = expect_context();
+
+    let is_starred = move || mailroom.is_starred(id);
+
+    view! {
+        <button class="px-1 hover:text-white"
+            title=move || if is_starred() { "Unstar" } else { "Star" }
+            on:click=move |_| {
+                mailroom.toggle_starred(id);
+            }>
+            {move || if is_starred() { "★" } else { "☆" }}
+        </button>
+    }
+}
+
+#[component]
+fn Chat() -> impl IntoView {
+    let mailroom: Mailroom = expect_context();
 
     create_effect(move |_| {
-        messages(); // track on messages
-                    // only do this once rendered
-        if let Some(div) = messages_element() {
-            let bottom = div.scroll_height() - div.client_height();
-            let scroll_top = div.scroll_top();
-            if scrolled_bottom.get_untracked() && scroll_top < bottom {
-                div.set_scroll_top(bottom);
+        register_handler(move |chat_msg: ChatMessage| {
+            let focused = document().has_focus().unwrap_or(false);
+            if let Some((title, body)) = mailroom.notification_for(&chat_msg, focused) {
+                show_notification(&title, &body);
             }
-        };
-    });
+            mailroom.add_message(chat_msg);
+        });
 
-    // todo: do flair properly
+        register_handler(move |edit: ChatMessageEdited| {
+            mailroom.edit_message(edit);
+        });
 
-    // ¡WARNING! CSS is sometimes smoking something very strong.
-    //           Below the `grow` AND some height i.e. `h-1` must be present
-    //           `grow` makes the flexbox grow to fill the screen.
-    //           `h-1` basically sets the minimum height to like 1 pixel (technically it sets `height: 0.25rem`)
+        register_handler(move |deletion: ChatMessageDeleted| {
+            mailroom.delete_message(deletion);
+        });
+
+        register_handler(move |started: TypingStarted| {
+            mailroom.start_typing(started, Date::now());
+        });
+
+        register_handler(move |stopped: TypingStopped| {
+            mailroom.stop_typing(stopped);
+        });
+
+        register_handler(move |reply: ThreadReply| {
+            mailroom.add_thread_reply(reply);
+        });
+
+        register_handler(move |reaction: ReactionAdded| {
+            mailroom.add_reaction(reaction);
+        });
+
+        register_handler(move |reaction: ReactionRemoved| {
+            mailroom.remove_reaction(reaction);
+        });
+
+        register_handler(move |history: History| {
+            mailroom.add_history(history);
+        });
+
+        register_handler(move |pins: ChannelPins| {
+            mailroom.set_pins(pins);
+        });
+
+        register_handler(move |pinned: MessagePinned| {
+            mailroom.pin_message(pinned);
+        });
+
+        register_handler(move |unpinned: MessageUnpinned| {
+            mailroom.unpin_message(unpinned);
+        });
+    });
+
+    let active_messages = move || mailroom.active_messages();
+
+    // typing events can get lost, so expire them ourselves
+    let prune_handle = set_interval_with_handle(
+        move || {
+            mailroom.prune_typing(Date::now());
+        },
+        Duration::from_secs(1),
+    )
+    .ok();
+    on_cleanup(move || {
+        if let Some(handle) = prune_handle {
+            handle.clear();
+        }
+    });
+
+    // only messages someone could actually be looking at count as read
+    let focused = create_rw_signal(document().has_focus().unwrap_or(false));
+    let focus_handle = window_event_listener(ev::focus, move |_| focused.set(true));
+    let blur_handle = window_event_listener(ev::blur, move |_| focused.set(false));
+    on_cleanup(move || {
+        focus_handle.remove();
+        blur_handle.remove();
+    });
+    let scrolled_bottom = create_rw_signal(true);
+    // what's been scrolled back through stays until we're back at the newest message
+    create_effect(move |_| mailroom.set_at_bottom(scrolled_bottom()));
+
+    // the newest message counts as read once it's in view, let the server know so other sessions agree
+    create_effect(move |_| {
+        if !focused() || !scrolled_bottom() {
+            return;
+        }
+        if let Some((mailbox, message_id)) = mailroom.mark_active_read() {
+            send_message(MarkRead {
+                mailbox,
+                message_id,
+            });
+        }
+    });
+
+    let get_chat_title = move || mailroom.active_display_name();
+
+    let get_typing_line = move || {
+        let usernames = mailroom.active_typing_usernames();
+        match usernames.as_slice() {
+            [] => "".to_string(),
+            [one] => format!("{one} is typing…"),
+            [one, two] => format!("{one} and {two} are typing…"),
+            _ => "several people are typing…".to_string(),
+        }
+    };
+
+    let get_membership_action = move || match mailroom.active_selection() {
+        // nothing to offer until the server says whether we're in it
+        SendableId::C(cid) if mailroom.membership(cid).is_none() => None,
+        SendableId::C(cid) if mailroom.is_member(cid) => Some(view! {
+            <button class="px-2 text-sm text-rose-500 hover:underline"
+                on:click=move |_| send_message(LeaveChannel { id: cid })>
+                Leave
+            </button>
+        }),
+        SendableId::C(cid) => Some(view! {
+            <button class="px-2 text-sm text-green-400 hover:underline"
+                on:click=move |_| send_message(JoinChannel { id: cid })>
+                Join
+            </button>
+        }),
+        _ => None,
+    };
+
+    // stays mounted while browsing so the handlers above aren't registered twice
+    let get_archived_badge = move || match mailroom.active_selection() {
+        SendableId::C(cid) if mailroom.is_archived(cid) => Some(view! {
+            <span class="mx-2 my-2 px-1 text-xs rounded bg-emerald-900 text-amber-100">
+                archived
+            </span>
+        }),
+        _ => None,
+    };
+
+    let active_selection = create_memo(move |_| mailroom.active_selection());
+
+    let (editing_topic, set_editing_topic) = create_signal(false);
+    let (new_topic, set_new_topic) = create_signal("".to_string());
+    let (new_description, set_new_description) = create_signal("".to_string());
+
+    let get_topic_action = move || {
+        if !mailroom.active_selection().is_channel() || !mailroom.can_send_to_active() {
+            return None;
+        }
+        Some(view! {
+            <button class="px-2 text-sm text-amber-300 hover:underline"
+                on:click=move |_| {
+                    let (topic, description) = mailroom.active_topic().unwrap_or_default();
+                    set_new_topic(topic);
+                    set_new_description(description);
+                    set_editing_topic(!editing_topic.get_untracked());
+                }>
+                Topic
+            </button>
+        })
+    };
+
+    let get_topic = move || {
+        if editing_topic() {
+            return view! {
+                <form class="flex flex-row mx-2 mb-1 text-sm"
+                    on:submit=move |evt| {
+                        evt.prevent_default();
+                        if let SendableId::C(cid) = mailroom.active_selection() {
+                            send_message(SetChannelTopic {
+                                id: cid,
+                                topic: new_topic(),
+                                description: new_description(),
+                            });
+                        }
+                        set_editing_topic(false);
+                    }
+                >
+                    <input class="p-1 mr-2 basis-1/3 rounded text-white bg-emerald-900"
+                        type="text"
+                        placeholder="Topic"
+                        on:input=move |evt| set_new_topic(event_target_value(&evt))
+                        prop:value=new_topic
+                    />
+                    <input class="p-1 mr-2 grow rounded text-white bg-emerald-900"
+                        type="text"
+                        placeholder="Description"
+                        on:input=move |evt| set_new_description(event_target_value(&evt))
+                        prop:value=new_description
+                    />
+                    <button class="bg-amber-500 hover:bg-amber-700 text-white px-2 rounded">Set</button>
+                </form>
+            }
+            .into_view();
+        }
+        match mailroom.active_topic() {
+            Some((topic, description)) if !topic.is_empty() || !description.is_empty() => view! {
+                <div class="mx-2 mb-1 text-amber-100">
+                    <div class="text-sm">{topic}</div>
+                    <div class="text-xs truncate" title=description.clone()>{description}</div>
+                </div>
+            }
+            .into_view(),
+            _ => ().into_view(),
+        }
+    };
+
+    let get_notify_select = move || {
+        let level = mailroom.notify_level(active_selection());
+        view! {
+            <select class="mx-2 px-1 text-sm rounded text-amber-300 bg-emerald-900"
+                title="Notifications"
+                on:change=move |evt| {
+                    let label = event_target_value(&evt);
+                    if let Some(level) = NotifyLevel::ALL.into_iter().find(|l| l.label() == label) {
+                        mailroom.set_notify_level(mailroom.active_selection(), level);
+                    }
+                }>
+                {NotifyLevel::ALL
+                    .into_iter()
+                    .map(|option| {
+                        view! {
+                            <option value=option.label() selected=option == level>
+                                {option.label()}
+                            </option>
+                        }
+                    })
+                    .collect_view()}
+            </select>
+        }
+    };
+
+    let (showing_pins, set_showing_pins) = create_signal(false);
+
+    let get_pins_action = move || {
+        active_selection().is_channel().then(|| {
+            view! {
+                <button class="px-2 text-sm text-amber-300 hover:underline"
+                    on:click=move |_| set_showing_pins(!showing_pins.get_untracked())>
+                    "📌 Pins"
+                </button>
+            }
+        })
+    };
+
+    let get_pins = move || {
+        if !showing_pins() {
+            return None;
+        }
+        let mailbox_id = active_selection();
+        let pins = mailroom.pins(mailbox_id);
+        let body = if pins.is_empty() {
+            view! { <i class="m-1 p-1 block">"Nothing pinned yet"</i> }.into_view()
+        } else {
+            pins.into_iter()
+                .map(|chat| view! { <MessagePreview mailbox_id=mailbox_id chat=chat /> })
+                .collect_view()
+        };
+        Some(view! {
+            <div class="mx-2 mb-1 p-1 max-h-64 rounded-lg bg-emerald-900 text-amber-100 overflow-y-scroll">
+                {body}
+            </div>
+        })
+    };
+
+    let (exporting, set_exporting) = create_signal(false);
+    let (export_format, set_export_format) = create_signal(ExportFormat::Markdown);
+    let (export_after, set_export_after) = create_signal("".to_string());
+    let (export_before, set_export_before) = create_signal("".to_string());
+
+    let get_export_form = move || {
+        exporting().then(|| {
+            view! {
+                <form class="flex flex-row mx-2 mb-1 text-sm text-amber-100 items-center"
+                    on:submit=move |evt| {
+                        evt.prevent_default();
+                        let after = parse_date(&export_after());
+                        // before is inclusive of the whole day
+                        let before = parse_date(&export_before()).map(|ts| ts + MS_PER_DAY);
+                        if let Some(transcript) =
+                            mailroom.transcript(mailroom.active_selection(), after, before)
+                        {
+                            transcript.download(export_format.get_untracked());
+                        }
+                        set_exporting(false);
+                    }
+                >
+                    <select class="p-1 mr-2 rounded text-white bg-emerald-900"
+                        on:change=move |evt| {
+                            let label = event_target_value(&evt);
+                            if let Some(format) =
+                                ExportFormat::ALL.into_iter().find(|f| f.label() == label)
+                            {
+                                set_export_format(format);
+                            }
+                        }>
+                        {ExportFormat::ALL
+                            .into_iter()
+                            .map(|format| {
+                                view! {
+                                    <option value=format.label()
+                                        selected=move || export_format() == format>
+                                        {format.label()}
+                                    </option>
+                                }
+                            })
+                            .collect_view()}
+                    </select>
+                    <span class="mr-1">from</span>
+                    <input class="p-1 mr-2 rounded text-white bg-emerald-900"
+                        type="date"
+                        on:input=move |evt| set_export_after(event_target_value(&evt))
+                        prop:value=export_after
+                    />
+                    <span class="mr-1">to</span>
+                    <input class="p-1 mr-2 rounded text-white bg-emerald-900"
+                        type="date"
+                        on:input=move |evt| set_export_before(event_target_value(&evt))
+                        prop:value=export_before
+                    />
+                    <button class="bg-amber-500 hover:bg-amber-700 text-white px-2 rounded">
+                        Download
+                    </button>
+                    {move || {
+                        let after = parse_date(&export_after());
+                        mailroom.is_transcript_partial(active_selection(), after).then(|| view! {
+                            <span class="ml-2 text-xs text-rose-400">
+                                "Only loaded messages are exported, scroll back for older ones"
+                            </span>
+                        })
+                    }}
+                </form>
+            }
+        })
+    };
+
+    // close the topic editor and export form when switching mailboxes
+    create_effect(move |_| {
+        active_selection();
+        set_editing_topic(false);
+        set_exporting(false);
+        set_showing_pins(false);
+    });
+
+    view! {
+        <div class="basis-3/4 overflow-hidden flex flex-col bg-green-950 rounded-r-md"
+            class:hidden=move || mailroom.is_browsing()>
+            <div class="flex flex-row">
+                <h1 class="mx-2 my-1 text-xl font-bold text-amber-300">{get_chat_title}</h1>
+                {get_archived_badge}
+                <div class="grow"></div>
+                {get_notify_select}
+                {get_pins_action}
+                <button class="px-2 text-sm text-amber-300 hover:underline"
+                    on:click=move |_| set_exporting(!exporting.get_untracked())>
+                    Export
+                </button>
+                {get_topic_action}
+                {get_membership_action}
+            </div>
+            {get_export_form}
+            {get_pins}
+            {get_topic}
+            <DisplayMessages
+                messages={active_messages}
+                divider_after=Signal::derive(move || mailroom.active_read_divider())
+                notices_after=Callback::new(move |after: Option<String>| {
+                    mailroom.active_notices_after(after.as_deref())
+                })
+                scrolled_bottom=scrolled_bottom
+                on_scroll_top=Callback::new(move |_| {
+                    if let Some((mailbox, before)) = mailroom.start_loading_history() {
+                        send_message(FetchHistory {
+                            mailbox,
+                            before,
+                            limit: HISTORY_PAGE_SIZE,
+                        });
+                    }
+                }) />
+            <div class="mx-3 h-5 text-xs italic text-amber-100">{get_typing_line}</div>
+            <ChatInput />
+        </div>
+    }
+}
+
+#[component]
+fn BrowseChannels() -> impl IntoView {
+    let mailroom: Mailroom = expect_context();
+
+    let get_channels = move || mailroom.browse_channel_list();
+
+    let get_empty_note = move || {
+        let note = if mailroom.has_unknown_membership() {
+            "Checking which channels you're in…"
+        } else {
+            "You're in every channel already"
+        };
+        get_channels()
+            .is_empty()
+            .then(|| view! { <i class="m-1 p-1 block">{note}</i> })
+    };
+
+    view! {
+        <div class="basis-3/4 overflow-hidden flex flex-col bg-green-950 rounded-r-md">
+            <div class="flex flex-row">
+                <h1 class="mx-2 my-1 grow text-xl font-bold text-amber-300">Browse channels</h1>
+                <button class="px-2 font-bold text-rose-500 hover:underline"
+                    on:click=move |_| {
+                        mailroom.set_browsing(false);
+                    }>
+                    X
+                </button>
+            </div>
+            <div class="m-2 p-1 bg-emerald-900 grow h-1 rounded-lg text-amber-100 overflow-y-scroll">
+                {get_empty_note}
+                <For
+                    each=get_channels
+                    key=|(cid, _)| *cid
+                    children=move |(cid, name): (ChannelId, String)| {
+                        view! {
+                            <div class="m-1 p-1 flex flex-row rounded hover:bg-emerald-950">
+                                <span class="grow">"#" {name}</span>
+                                <button class="px-2 rounded bg-amber-500 hover:bg-amber-700 text-white"
+                                    on:click=move |_| {
+                                        send_message(JoinChannel { id: cid });
+                                        mailroom.set_active(cid);
+                                    }>
+                                    Join
+                                </button>
+                            </div>
+                        }
+                    }
+                />
+            </div>
+        </div>
+    }
+}
+
+#[component]
+fn MemberList() -> impl IntoView {
+    let mailroom: Mailroom = expect_context();
+
+    let get_members = move || match mailroom.active_selection() {
+        SendableId::C(cid) if !mailroom.is_browsing() => Some(mailroom.member_list(cid)),
+        SendableId::G(gid) if !mailroom.is_browsing() => Some(mailroom.group_member_list(gid)),
+        _ => None,
+    };
+    // only DMs and the browse view hide the member list
+    let showing = create_memo(move |_| get_members().is_some());
+
+    move || {
+        showing().then(|| {
+            view! {
+                <div class="basis-1/6 ml-2 text-amber-300 bg-green-950 rounded-md flex flex-col">
+                    <h2 class="font-bold text-lg mx-2 pt-2 pl-2">Members</h2>
+                    <div class="grow h-1 bg-emerald-900 m-2 p-1 rounded-lg overflow-y-scroll">
+                        <For
+                            each=move || get_members().unwrap_or_default()
+                            key=|(uid, _)| *uid
+                            let:child>
+                            <DisplayUser
+                                user_id=child.0
+                                username=child.1 />
+                        </For>
+                    </div>
+                </div>
+            }
+        })
+    }
+}
+
+#[component]
+fn DisplayMessages<F: Fn() -> Vec<Rc<ChatMessage>> + Copy + 'static>(
+    messages: F,
+    // draws a "new since you were last here" line after this message
+    #[prop(optional)] divider_after: Option<Signal<Option<String>>>,
+    // called when scrolled all the way up, to load older messages
+    #[prop(optional)] on_scroll_top: Option<Callback<()>>,
+    // for the caller to know whether the newest message is in view
+    #[prop(optional)] scrolled_bottom: Option<RwSignal<bool>>,
+    // notices like topic changes after the message with this id, or above them all for None
+    #[prop(optional)] notices_after: Option<Callback<Option<String>, Vec<String>>>,
+) -> impl IntoView {
+    let scrolled_bottom = scrolled_bottom.unwrap_or_else(|| create_rw_signal(true));
+    let messages_element: NodeRef<Div> = create_node_ref();
+    // to keep our place when older messages are added above
+    let first_message_id = store_value(None::<String>);
+    let last_scroll_height = store_value(0);
+
+    create_effect(move |_| {
+        // track on messages
+        let messages = messages();
+        let first_id = messages.first().map(|msg| msg.id.clone());
+        // only do this once rendered
+        if let Some(div) = messages_element() {
+            let bottom = div.scroll_height() - div.client_height();
+            let scroll_top = div.scroll_top();
+            // the old first message moved down, rather than a different mailbox showing up
+            let prepended = first_message_id.with_value(|old_first_id| {
+                old_first_id.as_ref().is_some_and(|old_first_id| {
+                    messages.iter().skip(1).any(|msg| msg.id == *old_first_id)
+                })
+            });
+            if prepended && !scrolled_bottom.get_untracked() {
+                div.set_scroll_top(
+                    scroll_top + div.scroll_height() - last_scroll_height.get_value(),
+                );
+            } else if scrolled_bottom.get_untracked() && scroll_top < bottom {
+                div.set_scroll_top(bottom);
+            }
+            last_scroll_height.set_value(div.scroll_height());
+        };
+        first_message_id.set_value(first_id);
+    });
+
+    let show_notices = move |after: Option<String>| {
+        move || {
+            notices_after.map(|notices_after| {
+                notices_after
+                    .call(after.clone())
+                    .into_iter()
+                    .map(|notice| {
+                        view! { <div class="mx-4 my-1 text-xs italic text-amber-100/50">{notice}</div> }
+                    })
+                    .collect_view()
+            })
+        }
+    };
+
+    // todo: do flair properly
+
+    // ¡WARNING! CSS is sometimes smoking something very strong.
+    //           Below the `grow` AND some height i.e. `h-1` must be present
+    //           `grow` makes the flexbox grow to fill the screen.
+    //           `h-1` basically sets the minimum height to like 1 pixel (technically it sets `height: 0.25rem`)
     //           This might seem contradictory...
     //           Or perhaps one overrides the other, and thus `h-1` needs to be cleaned up and removed...
     //           You'd be wrong, like I was.
@@ -468,32 +1739,94 @@ fn DisplayMessages<F: Fn() -> Vec<ChatMessage> + Copy + 'static>(messages: F) ->
                 let scroll_top = div.scroll_top();
                 let bottom = div.scroll_height() - div.client_height();
                 // if we're within 17 pixels of the bottom, consider us at the bottom
-                if (bottom - scroll_top).abs() <= 17 {
-                    set_scrolled_bottom(true);
-                } else {
-                    set_scrolled_bottom(false);
+                let at_bottom = (bottom - scroll_top).abs() <= 17;
+                if scrolled_bottom.get_untracked() != at_bottom {
+                    scrolled_bottom.set(at_bottom);
+                }
+                last_scroll_height.set_value(div.scroll_height());
+                if scroll_top == 0 {
+                    if let Some(on_scroll_top) = on_scroll_top {
+                        on_scroll_top.call(());
+                    }
                 }
             }
             node_ref=messages_element>
+            {show_notices(None)}
             <For
                 each=messages
                 key=|chat_msg| chat_msg.id.clone()
-                let:child
-            >
-                <DisplayChatMessage chat=child />
-            </For>
+                children=move |chat_msg: Rc<ChatMessage>| {
+                    let notices = show_notices(Some(chat_msg.id.clone()));
+                    let message_id = chat_msg.id.clone();
+                    let show_divider = move || {
+                        divider_after
+                            .map(|divider| divider.with(|d| d.as_ref() == Some(&message_id)))
+                            .unwrap_or(false)
+                    };
+                    view! {
+                        <DisplayChatMessage chat=ChatMessage::clone(&chat_msg) />
+                        {notices}
+                        {move || show_divider().then(|| view! {
+                            <div class="mx-2 my-1 flex flex-row items-center text-xs text-rose-400">
+                                <div class="grow border-t border-rose-400"></div>
+                                <span class="px-2">"new since you were last here"</span>
+                                <div class="grow border-t border-rose-400"></div>
+                            </div>
+                        })}
+                    }
+                }
+            />
         </div>
     }
 }
 
+const REACTION_EMOJIS: [&str; 8] = ["👍", "👎", "😂", "❤️", "🎉", "😮", "😢", "🐢"];
+
 #[component]
-fn DisplayChatMessage(chat: ChatMessage) -> impl IntoView {
-    let mailroom: ReadSignal<Mailroom> = expect_context();
+fn DisplayChatMessage(
+    chat: ChatMessage,
+    // hides the thread actions when the message is already shown inside a thread
+    #[prop(optional)] in_thread: bool,
+) -> impl IntoView {
+    let mailroom: Mailroom = expect_context();
+    let message_id = store_value(chat.id.clone());
+    let initial_content = chat.content.clone();
+    // rows are keyed by id, so edits and deletes come in through here rather than remounting
+    let content = create_memo(move |_| {
+        message_id
+            .with_value(|id| mailroom.message_content(id))
+            .unwrap_or(initial_content.clone())
+    });
+    let (editing, set_editing) = create_signal(false);
+    let (show_picker, set_show_picker) = create_signal(false);
+    let (edited_content, set_edited_content) = create_signal(chat.content.clone());
+    let edit_ref: NodeRef<Input> = create_node_ref();
+    let row_ref: NodeRef<Div> = create_node_ref();
+
+    // focus the edit box as soon as it shows up
+    create_effect(move |_| {
+        if editing() {
+            if let Some(input) = edit_ref() {
+                let _ = input.focus();
+            }
+        }
+    });
+
+    let is_highlighted = move || message_id.with_value(|id| mailroom.is_highlighted(id));
+    // scroll to the message when a search result jumps here
+    create_effect(move |_| {
+        if is_highlighted() {
+            if let Some(row) = row_ref() {
+                request_animation_frame(move || row.scroll_into_view());
+            }
+        }
+    });
+
     let get_username_and_flair = move |from| {
-        let mailroom = mailroom();
         let maybe_user = mailroom.get_user(from);
         if let Some(user) = maybe_user {
-            (user.username, user.flair)
+            let name = mailroom.user_display_name(from).unwrap_or(user.username);
+            (name, user.flair)
         } else if let UserId(0) = from {
             ("server".to_string(), Some("🖥️".to_string()))
         } else {
@@ -503,52 +1836,795 @@ fn DisplayChatMessage(chat: ChatMessage) -> impl IntoView {
     let get_datetime = move || Date::new(chat.ts).to_locale_string().to_string();
     let get_user_display = move || {
         let (username, flair) = get_username_and_flair(chat.from);
+        // hovering a nickname or display name shows who it really is
+        let real_username = mailroom.hidden_username(chat.from);
+        let avatar = mailroom.avatar(chat.from).map(|src| {
+            view! { <img class="inline-block w-5 h-5 mr-1 rounded-full" src=src alt="" /> }
+        });
         view! {
             <div>
-                <b>"[" {username} "] " {flair} </b>
+                {avatar}
+                <b title=real_username>"[" {username} "] " {flair} </b>
                 <span class="px-1 text-xs">{get_datetime}</span>
             </div>
         }
     };
 
+    let is_own = move || mailroom.current_user_id() == Some(chat.from);
+    // ignored users' messages stay collapsed unless asked for
+    let (revealed, set_revealed) = create_signal(false);
+    let is_collapsed = move || mailroom.is_ignored(chat.from) && !revealed();
+    let is_deleted = move || message_id.with_value(|id| mailroom.is_deleted(id));
+    let is_edited = move || message_id.with_value(|id| mailroom.is_edited(id));
+    let is_pinned = move || message_id.with_value(|id| mailroom.is_pinned(id));
+    let is_bookmarked = move || message_id.with_value(|id| mailroom.is_bookmarked(id));
+
+    let get_content = move || {
+        if is_deleted() {
+            view! { <i class="text-amber-100/50">"message deleted"</i> }.into_view()
+        } else if editing() {
+            view! {
+                <form class="flex grow"
+                    on:submit=move |evt| {
+                        evt.prevent_default();
+                        let new_content = edited_content();
+                        if new_content.len() > 0 && new_content != content.get_untracked() {
+                            send_message(EditChatMessage {
+                                id: message_id.get_value(),
+                                content: new_content,
+                            });
+                        }
+                        set_editing(false);
+                    }
+                >
+                    <input class="p-1 grow rounded text-white bg-emerald-950"
+                        type="text"
+                        on:input=move |evt| {
+                            set_edited_content(event_target_value(&evt).to_string());
+                        }
+                        on:keydown=move |evt| {
+                            if evt.key() == "Escape" {
+                                set_editing(false);
+                            }
+                        }
+                        prop:value=edited_content
+                        node_ref=edit_ref
+                    />
+                </form>
+            }
+            .into_view()
+        } else {
+            view! {
+                <div>
+                    " - " <MessageText content=content() />
+                    {move || is_edited().then(|| view! {
+                        <span class="px-1 text-xs text-amber-100/60">"(edited)"</span>
+                    })}
+                    {move || is_pinned().then(|| view! {
+                        <span class="px-1 text-xs" title="Pinned">"📌"</span>
+                    })}
+                    {move || is_bookmarked().then(|| view! {
+                        <span class="px-1 text-xs" title="Saved">"🔖"</span>
+                    })}
+                </div>
+            }
+            .into_view()
+        }
+    };
+
+    let toggle_reaction = move |emoji: String, already_reacted: bool| {
+        let message_id = message_id.get_value();
+        if already_reacted {
+            send_message(RemoveReaction { message_id, emoji });
+        } else {
+            send_message(AddReaction { message_id, emoji });
+        }
+    };
+
+    let open_thread = move || {
+        mailroom.open_thread(message_id.get_value());
+    };
+
+    let get_thread_summary = move || {
+        if in_thread {
+            return None;
+        }
+        let count = message_id.with_value(|id| mailroom.reply_count(id));
+        if count == 0 {
+            return None;
+        }
+        let replies = if count == 1 { "reply" } else { "replies" };
+        Some(view! {
+            <button class="ml-4 w-fit text-xs text-amber-300 hover:underline"
+                on:click=move |_| open_thread()>
+                "💬 " {count} " " {replies}
+            </button>
+        })
+    };
+
+    let get_actions = move || {
+        if is_deleted() || editing() {
+            return None;
+        }
+        let own_actions = is_own().then(|| {
+            view! {
+                <button class="px-1 hover:underline"
+                    on:click=move |_| {
+                        set_edited_content(content.get_untracked());
+                        set_editing(true);
+                    }>
+                    edit
+                </button>
+                <button class="px-1 hover:underline text-rose-400"
+                    on:click=move |_| {
+                        let confirmed = window()
+                            .confirm_with_message("Delete this message?")
+                            .unwrap_or(false);
+                        if confirmed {
+                            send_message(DeleteChatMessage {
+                                id: message_id.get_value(),
+                            });
+                        }
+                    }>
+                    delete
+                </button>
+            }
+        });
+        // pins belong to channels
+        let pin_action = chat.to.is_channel().then(|| {
+            let pinned = is_pinned();
+            view! {
+                <button class="px-1 hover:underline"
+                    on:click=move |_| {
+                        let message_id = message_id.get_value();
+                        if pinned {
+                            send_message(UnpinMessage { message_id });
+                        } else {
+                            send_message(PinMessage { message_id });
+                        }
+                    }>
+                    {if pinned { "unpin" } else { "pin" }}
+                </button>
+            }
+        });
+        let thread_action = (!in_thread).then(|| {
+            view! {
+                <button class="px-1 hover:underline" on:click=move |_| open_thread()>
+                    reply
+                </button>
+            }
+        });
+        Some(view! {
+            <div class="invisible group-hover:visible ml-auto pl-2 text-xs whitespace-nowrap">
+                <button class="px-1 hover:underline"
+                    on:click=move |_| set_show_picker(!show_picker.get_untracked())>
+                    react
+                </button>
+                {thread_action}
+                {pin_action}
+                <button class="px-1 hover:underline"
+                    on:click=move |_| {
+                        message_id.with_value(|id| mailroom.toggle_bookmark(id));
+                    }>
+                    {if is_bookmarked() { "unsave" } else { "save" }}
+                </button>
+                {own_actions}
+            </div>
+        })
+    };
+
+    let get_picker = move || {
+        if !show_picker() {
+            return None;
+        }
+        Some(view! {
+            <div class="flex flex-row ml-4 p-1 w-fit rounded bg-emerald-950">
+                {REACTION_EMOJIS
+                    .into_iter()
+                    .map(|emoji| {
+                        // picking one we've already used takes it back, like its chip
+                        let reacted =
+                            move || message_id.with_value(|id| mailroom.has_reacted(id, emoji));
+                        let css_class = move || {
+                            if reacted() {
+                                "px-1 rounded bg-emerald-700"
+                            } else {
+                                "px-1 rounded hover:bg-emerald-700"
+                            }
+                        };
+                        view! {
+                            <button class=css_class
+                                on:click=move |_| {
+                                    toggle_reaction(emoji.to_string(), reacted());
+                                    set_show_picker(false);
+                                }>
+                                {emoji}
+                            </button>
+                        }
+                    })
+                    .collect_view()}
+            </div>
+        })
+    };
+
+    let get_reactions = move || {
+        let reactions = message_id.with_value(|id| mailroom.reactions(id));
+        if reactions.is_empty() {
+            return None;
+        }
+        let current_user_id = mailroom.current_user_id();
+        Some(view! {
+            <div class="flex flex-row flex-wrap ml-4 text-sm">
+                {reactions
+                    .into_iter()
+                    .map(|(emoji, user_ids)| {
+                        let reacted = current_user_id
+                            .map(|id| user_ids.contains(&id))
+                            .unwrap_or(false);
+                        // who reacted, shown on hover
+                        let who = user_ids
+                            .iter()
+                            .map(|uid| {
+                                mailroom
+                                    .user_display_name(*uid)
+                                    .unwrap_or("unknown user".to_string())
+                            })
+                            .collect::<Vec<_>>()
+                            .join(", ");
+                        let css_class = if reacted {
+                            "mr-1 px-1 rounded border border-amber-300 bg-emerald-700"
+                        } else {
+                            "mr-1 px-1 rounded border border-emerald-700 hover:bg-emerald-950"
+                        };
+                        let count = user_ids.len();
+                        let toggle_emoji = emoji.clone();
+                        view! {
+                            <button class=css_class
+                                title=who
+                                on:click=move |_| toggle_reaction(toggle_emoji.clone(), reacted)>
+                                {emoji} " " {count}
+                            </button>
+                        }
+                    })
+                    .collect_view()}
+            </div>
+        })
+    };
+
     view! {
-        <div class="m-1 p-1 flex flex-row">
-            {get_user_display}
-            <div> - {chat.content} </div>
+        {move || is_collapsed().then(|| view! {
+            <div class="m-1 p-1 text-sm italic text-amber-100/50">
+                "Message from an ignored user"
+                <button class="px-1 not-italic hover:underline" on:click=move |_| set_revealed(true)>
+                    show
+                </button>
+            </div>
+        })}
+        <div class="group m-1 p-1 flex flex-col rounded"
+            class:hidden=is_collapsed
+            class:bg-amber-900=is_highlighted
+            node_ref=row_ref
+        >
+            <div class="flex flex-row">
+                {get_user_display}
+                {get_content}
+                {get_actions}
+            </div>
+            {get_picker}
+            {get_reactions}
+            {get_thread_summary}
         </div>
     }
 }
 
+/// A compact message that jumps to where it was said when clicked
 #[component]
-fn ChatInput() -> impl IntoView {
-    let mailroom: ReadSignal<Mailroom> = expect_context();
-    let (current_msg, set_current_msg) = create_signal("".to_string());
+fn MessagePreview(mailbox_id: SendableId, chat: ChatMessage) -> impl IntoView {
+    let mailroom: Mailroom = expect_context();
 
-    let input_ref: NodeRef<Input> = create_node_ref();
+    let (mailbox_name, username) = {
+        let username = mailroom
+            .user_display_name(chat.from)
+            .unwrap_or("unknown user".to_string());
+        (
+            mailroom.display_name(mailbox_id).unwrap_or_default(),
+            username,
+        )
+    };
+    let message_id = store_value(chat.id);
+    let initial_content = chat.content;
+    let get_content = move || {
+        message_id
+            .with_value(|id| mailroom.message_content(id))
+            .unwrap_or(initial_content.clone())
+    };
 
-    // whenever active mailroom changes, .focus() the input
-    create_effect(move |_| {
-        mailroom.get_untracked().set_active_hook(move || {
-            if let Some(input) = input_ref.get_untracked() {
-                let _ = input.focus();
+    view! {
+        <button class="m-1 p-1 w-full text-left rounded hover:bg-emerald-950"
+            on:click=move |_| {
+                mailroom.jump_to(mailbox_id, message_id.get_value());
+            }>
+            <div class="text-xs">
+                <b>{mailbox_name}</b>
+                " [" {username} "] "
+                {Date::new(chat.ts).to_locale_string()}
+            </div>
+            <div class="truncate">{get_content}</div>
+        </button>
+    }
+}
+
+#[component]
+fn SavedItems() -> impl IntoView {
+    let mailroom: Mailroom = expect_context();
+
+    let showing = create_memo(move |_| mailroom.side_panel() == Some(SidePanel::Saved));
+    let get_saved_items = move || mailroom.saved_items();
+
+    move || {
+        showing().then(|| {
+            view! {
+                <div class="basis-1/3 ml-2 overflow-hidden flex flex-col bg-green-950 rounded-md">
+                    <div class="flex flex-row">
+                        <h2 class="mx-2 my-1 grow text-lg font-bold text-amber-300">Saved items</h2>
+                        <button class="px-2 font-bold text-rose-500 hover:underline"
+                            on:click=move |_| {
+                                mailroom.set_side_panel(None);
+                            }>
+                            X
+                        </button>
+                    </div>
+                    <div class="m-2 p-1 grow h-1 bg-emerald-900 rounded-lg text-amber-100 overflow-y-scroll">
+                        {move || get_saved_items().is_empty().then(|| view! {
+                            <i class="m-1 p-1 block">"Save messages to find them here"</i>
+                        })}
+                        <For
+                            each=get_saved_items
+                            key=|(mailbox_id, message_id, chat)| {
+                                (*mailbox_id, message_id.clone(), chat.is_some())
+                            }
+                            children=move |(mailbox_id, message_id, chat): (SendableId, String, Option<ChatMessage>)| {
+                                let preview = match chat {
+                                    Some(chat) => view! {
+                                        <MessagePreview mailbox_id=mailbox_id chat=chat />
+                                    }.into_view(),
+                                    None => view! {
+                                        <div class="m-1 p-1 text-xs">
+                                            <b>{mailroom.display_name(mailbox_id).unwrap_or_default()}</b>
+                                            <i>" message isn't loaded"</i>
+                                        </div>
+                                    }.into_view(),
+                                };
+                                let message_id = store_value(message_id);
+                                view! {
+                                    <div class="flex flex-row items-start">
+                                        <div class="grow min-w-0">{preview}</div>
+                                        <button class="m-1 px-1 text-xs hover:underline"
+                                            on:click=move |_| {
+                                                message_id.with_value(|id| mailroom.remove_bookmark(id));
+                                            }>
+                                            unsave
+                                        </button>
+                                    </div>
+                                }
+                            }
+                        />
+                    </div>
+                </div>
             }
-        });
+        })
+    }
+}
+
+#[component]
+fn Settings() -> impl IntoView {
+    let mailroom: Mailroom = expect_context();
+
+    let showing = create_memo(move |_| mailroom.side_panel() == Some(SidePanel::Settings));
+    let get_ignored_list = move || mailroom.ignored_list();
+    let (permission, set_permission) = create_signal(notification_permission());
+
+    move || {
+        showing().then(|| {
+            view! {
+                <div class="basis-1/3 ml-2 overflow-hidden flex flex-col bg-green-950 rounded-md">
+                    <div class="flex flex-row">
+                        <h2 class="mx-2 my-1 grow text-lg font-bold text-amber-300">Settings</h2>
+                        <button class="px-2 font-bold text-rose-500 hover:underline"
+                            on:click=move |_| {
+                                mailroom.set_side_panel(None);
+                            }>
+                            X
+                        </button>
+                    </div>
+                    <div class="m-2 p-2 grow h-1 bg-emerald-900 rounded-lg text-amber-100 overflow-y-scroll">
+                        <ProfileEditor />
+                        <h3 class="mt-4 font-bold text-amber-300">Notifications</h3>
+                        <label class="flex flex-row items-center">
+                            <span class="grow">"Desktop notifications"</span>
+                            <input type="checkbox"
+                                prop:checked=move || {
+                                    mailroom.desktop_notifications() && permission() == "granted"
+                                }
+                                on:change=move |evt| {
+                                    let enabled = event_target_checked(&evt);
+                                    mailroom.set_desktop_notifications(enabled);
+                                    if enabled && permission.get_untracked() != "granted" {
+                                        let done = Closure::once_into_js(move |granted: String| {
+                                            set_permission(granted);
+                                        });
+                                        request_notification_permission(done);
+                                    }
+                                }
+                            />
+                        </label>
+                        {move || (permission() == "denied").then(|| view! {
+                            <p class="text-xs text-amber-100/60">
+                                "Your browser is blocking notifications from this site."
+                            </p>
+                        })}
+                        <h3 class="mt-4 font-bold text-amber-300">Messages</h3>
+                        <label class="flex flex-row items-center">
+                            <span class="grow">"Format markdown (bold, code, links and so on)"</span>
+                            <input type="checkbox"
+                                prop:checked=move || !mailroom.show_plain_text()
+                                on:change=move |evt| {
+                                    mailroom.set_plain_text(!event_target_checked(&evt));
+                                }
+                            />
+                        </label>
+                        <label class="flex flex-row items-center">
+                            <span class="grow">"Messages kept per conversation"</span>
+                            <input class="p-1 w-24 rounded text-white bg-emerald-950"
+                                type="number"
+                                min="50"
+                                prop:value=move || mailroom.mailbox_capacity().to_string()
+                                on:change=move |evt| {
+                                    if let Ok(capacity) = event_target_value(&evt).parse::<usize>() {
+                                        mailroom.set_mailbox_capacity(capacity.max(50));
+                                    }
+                                }
+                            />
+                        </label>
+                        <p class="text-xs text-amber-100/60">
+                            "Older messages are dropped from memory and fetched again when you scroll back. "
+                            "Search reaches a few times further back."
+                        </p>
+                        <h3 class="mt-4 font-bold text-amber-300">Ignored users</h3>
+                        {move || get_ignored_list().is_empty().then(|| view! {
+                            <i class="m-1 p-1 block">"Nobody, ignore someone from the user list"</i>
+                        })}
+                        <For
+                            each=get_ignored_list
+                            key=|(uid, username)| (*uid, username.clone())
+                            children=move |(uid, username): (UserId, String)| {
+                                view! {
+                                    <div class="flex flex-row m-1 p-1">
+                                        <span class="grow">{username}</span>
+                                        <button class="px-1 text-sm hover:underline"
+                                            on:click=move |_| {
+                                                mailroom.toggle_ignored(uid);
+                                            }>
+                                            unignore
+                                        </button>
+                                    </div>
+                                }
+                            }
+                        />
+                    </div>
+                </div>
+            }
+        })
+    }
+}
+
+/// The current user's own display name and flair, everyone sees these
+#[component]
+fn ProfileEditor() -> impl IntoView {
+    let mailroom: Mailroom = expect_context();
+
+    let me = move || {
+        mailroom
+            .current_user_id()
+            .and_then(|id| mailroom.get_user(id))
+    };
+    let (display_name, set_display_name) = create_signal(String::new());
+    let (flair, set_flair) = create_signal(String::new());
+    // memo so other people's profile changes don't reset what's being typed
+    let saved = create_memo(move |_| me().map(|user| (user.display_name, user.flair)));
+    // start from what's set now, and follow along if it changes somewhere else
+    create_effect(move |_| {
+        if let Some((saved_display_name, saved_flair)) = saved() {
+            set_display_name(saved_display_name.unwrap_or_default());
+            set_flair(saved_flair.unwrap_or_default());
+        }
     });
 
+    let username = move || me().map(|user| format!("@{}", user.username));
+    // blank means unset
+    let non_empty = |value: String| {
+        let value = value.trim().to_string();
+        (!value.is_empty()).then_some(value)
+    };
+
+    view! {
+        <h3 class="font-bold text-amber-300">"Profile " <span class="text-sm font-normal">{username}</span></h3>
+        <form class="flex flex-col"
+            on:submit=move |evt| {
+                evt.prevent_default();
+                send_message(UpdateProfile {
+                    display_name: non_empty(display_name.get_untracked()),
+                    flair: non_empty(flair.get_untracked()),
+                });
+            }
+        >
+            <label class="flex flex-row items-center my-1">
+                <span class="grow">"Display name"</span>
+                <input class="p-1 w-40 rounded text-white bg-emerald-950" type="text"
+                    placeholder="your username"
+                    prop:value=display_name
+                    on:input=move |evt| set_display_name(event_target_value(&evt))
+                />
+            </label>
+            <label class="flex flex-row items-center my-1">
+                <span class="grow">"Flair"</span>
+                <input class="p-1 w-40 rounded text-white bg-emerald-950" type="text"
+                    placeholder="an emoji, say"
+                    prop:value=flair
+                    on:input=move |evt| set_flair(event_target_value(&evt))
+                />
+            </label>
+            <button class="self-end mt-1 px-2 bg-amber-500 hover:bg-emerald-700 text-white p-1 rounded">
+                Save
+            </button>
+        </form>
+    }
+}
+
+/// A message's text, formatted as markdown unless the user wants it plain
+#[component]
+fn MessageText(content: String) -> impl IntoView {
+    let mailroom: Mailroom = expect_context();
+    let content = store_value(content);
+
+    move || {
+        if mailroom.show_plain_text() {
+            return view! { <MentionText text=content.get_value() /> }.into_view();
+        }
+        let blocks = content.with_value(|content| markdown::parse(content));
+        match blocks.as_slice() {
+            // a one-liner stays on the line with the sender, like plain text does
+            [Block::Paragraph(inlines)] => render_inlines(mailroom, inlines.clone()),
+            _ => view! {
+                <div class="flex flex-col gap-1">{render_blocks(mailroom, blocks)}</div>
+            }
+            .into_view(),
+        }
+    }
+}
+
+// plain functions rather than components, these call themselves for nested blocks
+fn render_blocks(mailroom: Mailroom, blocks: Vec<Block>) -> View {
+    blocks
+        .into_iter()
+        .map(|block| match block {
+            Block::Paragraph(inlines) => {
+                view! { <p>{render_inlines(mailroom, inlines)}</p> }.into_view()
+            }
+            Block::Code { lang, code } => view! { <CodeBlock lang=lang code=code /> }.into_view(),
+            Block::Quote(quoted) => view! {
+                <blockquote class="pl-2 border-l-4 border-emerald-600 text-amber-100/80">
+                    {render_blocks(mailroom, quoted)}
+                </blockquote>
+            }
+            .into_view(),
+            Block::List { ordered, items } => {
+                let items = items
+                    .into_iter()
+                    .map(|item| view! { <li>{render_blocks(mailroom, item)}</li> })
+                    .collect_view();
+                if ordered {
+                    view! { <ol class="pl-6 list-decimal">{items}</ol> }.into_view()
+                } else {
+                    view! { <ul class="pl-6 list-disc">{items}</ul> }.into_view()
+                }
+            }
+        })
+        .collect_view()
+}
+
+fn render_inlines(mailroom: Mailroom, inlines: Vec<Inline>) -> View {
+    inlines
+        .into_iter()
+        .map(|inline| match inline {
+            Inline::Text(text) => view! { <MentionText text=text /> }.into_view(),
+            Inline::Strong(children) => {
+                view! { <strong>{render_inlines(mailroom, children)}</strong> }.into_view()
+            }
+            Inline::Emphasis(children) => {
+                view! { <em>{render_inlines(mailroom, children)}</em> }.into_view()
+            }
+            Inline::Code(code) => view! {
+                <code class="px-1 rounded bg-emerald-950 text-amber-200">{code}</code>
+            }
+            .into_view(),
+            Inline::Link { url, children } => view! {
+                <a class="underline text-sky-300 hover:text-sky-200"
+                    href=url
+                    target="_blank"
+                    rel="noopener noreferrer">
+                    {render_inlines(mailroom, children)}
+                </a>
+            }
+            .into_view(),
+            Inline::LineBreak => view! { <br /> }.into_view(),
+        })
+        .collect_view()
+}
+
+// blocks longer than this start out folded
+const CODE_COLLAPSE_LINES: usize = 20;
+
+/// A fenced code block, highlighted if it's in a language we know
+#[component]
+fn CodeBlock(lang: Option<String>, code: String) -> impl IntoView {
+    let language = lang.as_deref().and_then(Language::from_tag);
+    let label = language
+        .map(|language| language.label().to_string())
+        .or(lang)
+        .unwrap_or_default();
+    let line_count = code.lines().count();
+    let is_long = line_count > CODE_COLLAPSE_LINES;
+    let (expanded, set_expanded) = create_signal(false);
+    let (copied, set_copied) = create_signal(None::<bool>);
+
+    let runs = match language {
+        Some(language) => highlight_code(language, &code),
+        None => vec![(code.clone(), None)],
+    };
+    let highlighted = runs
+        .into_iter()
+        .map(|(text, kind)| match kind {
+            Some(kind) => view! { <span class=token_class(kind)>{text}</span> }.into_view(),
+            None => text.into_view(),
+        })
+        .collect_view();
+    let code = store_value(code);
+
+    view! {
+        <div class="rounded bg-emerald-950 text-amber-100">
+            <div class="flex flex-row px-2 pt-1 text-xs text-amber-100/60">
+                <span class="grow">{label}</span>
+                <button class="px-1 hover:text-white"
+                    on:click=move |_| {
+                        let done = Closure::once_into_js(move |ok: bool| {
+                            set_copied(Some(ok));
+                            set_timeout(move || set_copied(None), Duration::from_secs(2));
+                        });
+                        code.with_value(|code| copy_to_clipboard(code, done));
+                    }>
+                    {move || match copied() {
+                        Some(true) => "copied",
+                        Some(false) => "couldn't copy",
+                        None => "copy",
+                    }}
+                </button>
+            </div>
+            <pre class="px-2 pb-2 overflow-x-auto"
+                class:max-h-80=move || is_long && !expanded()
+                class:overflow-y-hidden=move || is_long && !expanded()>
+                <code>{highlighted}</code>
+            </pre>
+            {is_long.then(|| view! {
+                <button class="w-full px-2 pb-1 text-xs text-left text-amber-100/60 hover:text-white"
+                    on:click=move |_| set_expanded.update(|expanded| *expanded = !*expanded)>
+                    {move || if expanded() {
+                        "Show less".to_string()
+                    } else {
+                        format!("Show all {line_count} lines")
+                    }}
+                </button>
+            })}
+        </div>
+    }
+}
+
+fn token_class(kind: TokenKind) -> &'static str {
+    match kind {
+        TokenKind::Keyword => "text-fuchsia-300",
+        TokenKind::Type => "text-sky-300",
+        TokenKind::Str => "text-lime-300",
+        TokenKind::Number => "text-orange-300",
+        TokenKind::Comment => "italic text-amber-100/50",
+        TokenKind::Macro => "text-cyan-300",
+        TokenKind::Attribute => "text-amber-300/70",
+        TokenKind::Lifetime => "italic text-orange-200",
+        TokenKind::Error => "font-bold text-rose-400",
+        TokenKind::Warning => "font-bold text-amber-400",
+        TokenKind::Info => "text-sky-300",
+        TokenKind::Muted => "text-amber-100/50",
+    }
+}
+
+/// Text with known `@username`s turned into chips showing whatever we call them
+#[component]
+fn MentionText(text: String) -> impl IntoView {
+    let mailroom: Mailroom = expect_context();
+    let text = store_value(text);
+
+    move || {
+        text.with_value(|text| mailroom.split_mentions(text))
+            .into_iter()
+            .map(|(text, mentioned)| match mentioned {
+                Some(uid) => {
+                    let name = mailroom.user_display_name(uid).unwrap_or_default();
+                    view! {
+                        <span class="px-1 rounded bg-emerald-700 text-amber-300" title=text>
+                            "@" {name}
+                        </span>
+                    }
+                    .into_view()
+                }
+                None => text.into_view(),
+            })
+            .collect_view()
+    }
+}
+
+#[component]
+fn ThreadPanel() -> impl IntoView {
+    let mailroom: Mailroom = expect_context();
+
+    // memo so the panel (and its input) only rebuilds when a different thread is opened
+    let active_thread = create_memo(move |_| mailroom.active_thread());
+
+    move || {
+        active_thread().map(|parent_id| {
+            let parent_id = store_value(parent_id);
+            let get_parent = move || {
+                parent_id
+                    .with_value(|id| mailroom.get_message(id))
+                    .map(|chat| view! { <DisplayChatMessage chat=chat in_thread=true /> })
+            };
+            let thread_replies = move || parent_id.with_value(|id| mailroom.thread_replies(id));
+
+            view! {
+                <div class="basis-1/3 ml-2 overflow-hidden flex flex-col bg-green-950 rounded-md">
+                    <div class="flex flex-row">
+                        <h2 class="mx-2 my-1 grow text-lg font-bold text-amber-300">Thread</h2>
+                        <button class="px-2 font-bold text-rose-500 hover:underline"
+                            on:click=move |_| {
+                                mailroom.close_thread();
+                            }>
+                            X
+                        </button>
+                    </div>
+                    <div class="mx-2 text-amber-100 border-b border-emerald-700">
+                        {get_parent}
+                    </div>
+                    <DisplayMessages messages={thread_replies} />
+                    <ThreadInput parent_id=parent_id.get_value() />
+                </div>
+            }
+        })
+    }
+}
+
+#[component]
+fn ThreadInput(parent_id: String) -> impl IntoView {
+    let parent_id = store_value(parent_id);
+    let (current_msg, set_current_msg) = create_signal("".to_string());
+
     view! {
         <form class="mx-2 mt-0"
             on:submit=move |evt| {
                 evt.prevent_default();
                 let msg = current_msg();
                 if msg.len() > 0 {
-                    let mailroom = mailroom();
-                    let to = mailroom.active_selection();
-                    logging::log!("Sending a message to: {to:?}");
-                    let chat_msg = SendChatMessage {
-                        to,
-                        content: current_msg(),
-                    };
-                    send_message(chat_msg);
+                    send_message(SendThreadReply {
+                        parent_id: parent_id.get_value(),
+                        content: msg,
+                    });
                     set_current_msg("".to_string());
                 }
             }
@@ -556,10 +2632,188 @@ fn ChatInput() -> impl IntoView {
             <div class="flex flex-row py-3">
                 <input class="p-2 mr-2 rounded w-full text-white bg-emerald-900"
                     type="text"
+                    placeholder="Reply in thread"
                     on:input=move |evt| {
                         set_current_msg(event_target_value(&evt).to_string());
                     }
                     prop:value={current_msg}
+                />
+                <button class="bg-green-500 hover:bg-green-700 text-white font-bold px-2 rounded">
+                    Reply
+                </button>
+            </div>
+        </form>
+    }
+}
+
+// don't tell the server we're typing more often than this
+const TYPING_THROTTLE_MS: f64 = 3000.0;
+// and tell it we stopped if we haven't typed for this long
+const TYPING_IDLE: Duration = Duration::from_secs(5);
+// the message box grows this tall before it scrolls
+const MAX_INPUT_ROWS: usize = 8;
+
+#[component]
+fn ChatInput() -> impl IntoView {
+    let mailroom: Mailroom = expect_context();
+    // each mailbox keeps its own unsent message
+    let current_msg = move || mailroom.draft(mailroom.active_selection());
+
+    // a textarea so pasted code keeps its lines
+    let input_ref: NodeRef<Textarea> = create_node_ref();
+
+    // where and when we last sent a TypingStart
+    let typing_sent = store_value(None::<(SendableId, f64)>);
+    let idle_timeout = store_value(None::<TimeoutHandle>);
+
+    let stop_typing = move || {
+        if let Some(handle) = idle_timeout.get_value() {
+            handle.clear();
+            idle_timeout.set_value(None);
+        }
+        if let Some((to, _)) = typing_sent.get_value() {
+            send_message(TypingStop { to });
+            typing_sent.set_value(None);
+        }
+    };
+
+    let start_typing = move |to: SendableId| {
+        let now = Date::now();
+        match typing_sent.get_value() {
+            Some((sent_to, sent_at)) if sent_to == to && now - sent_at < TYPING_THROTTLE_MS => {}
+            _ => {
+                send_message(TypingStart { to });
+                typing_sent.set_value(Some((to, now)));
+            }
+        }
+        // restart the idle countdown
+        if let Some(handle) = idle_timeout.get_value() {
+            handle.clear();
+        }
+        idle_timeout.set_value(set_timeout_with_handle(stop_typing, TYPING_IDLE).ok());
+    };
+
+    // the `@name` being typed at the end of the message, if any
+    let mention_prefix = move || {
+        let msg = current_msg();
+        let last_word = msg.rsplit(char::is_whitespace).next().unwrap_or("");
+        last_word.strip_prefix('@').map(|prefix| prefix.to_string())
+    };
+    let get_suggestions = move || {
+        mention_prefix()
+            .map(|prefix| mailroom.mention_suggestions(&prefix))
+            .unwrap_or_default()
+    };
+    // swaps the partial `@name` for the full username
+    let complete_mention = move |username: String| {
+        let to = mailroom.active_selection();
+        let mut msg = mailroom.draft(to);
+        let partial = msg.rsplit(char::is_whitespace).next().unwrap_or("").len();
+        msg.truncate(msg.len() - partial);
+        msg.push_str(&format!("@{username} "));
+        mailroom.set_draft(to, msg);
+        if let Some(input) = input_ref.get_untracked() {
+            let _ = input.focus();
+        }
+    };
+
+    let get_suggestion_list = move || {
+        let suggestions = get_suggestions();
+        (!suggestions.is_empty()).then(|| {
+            view! {
+                <div class="flex flex-row flex-wrap pt-2 text-sm text-amber-100">
+                    {suggestions
+                        .into_iter()
+                        .map(|(username, nickname)| {
+                            let label = match nickname {
+                                Some(nickname) => format!("{nickname} (@{username})"),
+                                None => format!("@{username}"),
+                            };
+                            view! {
+                                <button class="mr-1 px-1 rounded bg-emerald-900 hover:bg-emerald-700"
+                                    type="button"
+                                    on:click=move |_| complete_mention(username.clone())>
+                                    {label}
+                                </button>
+                            }
+                        })
+                        .collect_view()}
+                </div>
+            }
+        })
+    };
+
+    // whenever active mailroom changes, .focus() the input
+    create_effect(move |_| {
+        mailroom.set_active_hook(move || {
+            // we're not typing in the old mailbox anymore
+            stop_typing();
+            if let Some(input) = input_ref.get_untracked() {
+                let _ = input.focus();
+            }
+        });
+    });
+
+    let send = move || {
+        let to = mailroom.active_selection();
+        let msg = mailroom.draft(to);
+        if !msg.trim().is_empty() {
+            logging::log!("Sending a message to: {to:?}");
+            stop_typing();
+            let chat_msg = SendChatMessage { to, content: msg };
+            send_message(chat_msg);
+            mailroom.set_draft(to, "".to_string());
+        }
+    };
+    // grow with the message, up to a point
+    let get_rows = move || (current_msg().matches('\n').count() + 1).min(MAX_INPUT_ROWS);
+
+    view! {
+        <form class="mx-2 mt-0"
+            on:submit=move |evt| {
+                evt.prevent_default();
+                send();
+            }
+        >
+            {get_suggestion_list}
+            <div class="flex flex-row py-3">
+                <textarea class="p-2 mr-2 rounded w-full resize-none text-white bg-emerald-900"
+                    rows=get_rows
+                    prop:disabled=move || !mailroom.can_send_to_active()
+                    placeholder=move || {
+                        match mailroom.active_selection() {
+                            _ if mailroom.can_send_to_active() => "",
+                            SendableId::C(cid) if mailroom.is_archived(cid) => {
+                                "This channel is archived"
+                            }
+                            _ => "Join this channel to send messages",
+                        }
+                    }
+                    on:input=move |evt| {
+                        let msg = event_target_value(&evt).to_string();
+                        let to = mailroom.active_selection();
+                        if msg.is_empty() {
+                            stop_typing();
+                        } else {
+                            start_typing(to);
+                        }
+                        mailroom.set_draft(to, msg);
+                    }
+                    on:keydown=move |evt| {
+                        // tab takes the first suggestion
+                        if evt.key() == "Tab" {
+                            if let Some((username, _)) = get_suggestions().into_iter().next() {
+                                evt.prevent_default();
+                                complete_mention(username);
+                            }
+                        }
+                        // enter sends, shift+enter starts a new line
+                        if evt.key() == "Enter" && !evt.shift_key() && !evt.is_composing() {
+                            evt.prevent_default();
+                            send();
+                        }
+                    }
+                    prop:value=current_msg
                     node_ref=input_ref
                 />
                 <button class="bg-green-500 hover:bg-green-700 text-white font-bold px-2 rounded">
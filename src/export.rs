@@ -0,0 +1,275 @@
+use crate::util::MS_PER_DAY;
+use serde::Serialize;
+use wasm_bindgen::prelude::wasm_bindgen;
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_name = downloadFile)]
+    fn download_file(filename: &str, mime_type: &str, contents: &str);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    Markdown,
+    Html,
+    Json,
+}
+
+impl ExportFormat {
+    pub const ALL: [ExportFormat; 3] = [
+        ExportFormat::Markdown,
+        ExportFormat::Html,
+        ExportFormat::Json,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ExportFormat::Markdown => "Markdown",
+            ExportFormat::Html => "HTML",
+            ExportFormat::Json => "JSON",
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Markdown => "md",
+            ExportFormat::Html => "html",
+            ExportFormat::Json => "json",
+        }
+    }
+
+    fn mime_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Markdown => "text/markdown",
+            ExportFormat::Html => "text/html",
+            ExportFormat::Json => "application/json",
+        }
+    }
+}
+
+/// A message with its sender already resolved, so the transcript stands on its own
+#[derive(Clone, Debug, Serialize)]
+pub struct ExportedMessage {
+    pub id: String,
+    pub username: String,
+    pub flair: Option<String>,
+    pub ts: f64,
+    pub timestamp: String,
+    pub content: String,
+    pub edited: bool,
+    /// Thread replies, oldest first
+    pub replies: Vec<ExportedMessage>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Transcript {
+    pub title: String,
+    pub messages: Vec<ExportedMessage>,
+}
+
+impl Transcript {
+    pub fn render(&self, format: ExportFormat) -> String {
+        match format {
+            ExportFormat::Markdown => self.to_markdown(),
+            ExportFormat::Html => self.to_html(),
+            ExportFormat::Json => serde_json::to_string_pretty(self).unwrap_or_default(),
+        }
+    }
+
+    /// Renders the transcript and hands it to the browser as a download
+    pub fn download(&self, format: ExportFormat) {
+        let name: String = self
+            .title
+            .chars()
+            .filter(|c| c.is_alphanumeric() || *c == '-' || *c == '_')
+            .collect();
+        let filename = format!("{name}.{}", format.extension());
+        download_file(&filename, format.mime_type(), &self.render(format));
+    }
+
+    fn to_markdown(&self) -> String {
+        let mut out = format!("# {}\n\n", self.title);
+        for message in &self.messages {
+            let flair = message
+                .flair
+                .as_ref()
+                .map(|flair| format!(" {flair}"))
+                .unwrap_or_default();
+            let edited = if message.edited { " _(edited)_" } else { "" };
+            // quote every line so multi-line messages stay together
+            let content = message.content.replace('\n', "\n> ");
+            out.push_str(&format!(
+                "**{}**{flair} · {}{edited}\n> {content}\n\n",
+                message.username, message.timestamp
+            ));
+            for reply in &message.replies {
+                let edited = if reply.edited { " _(edited)_" } else { "" };
+                let content = reply.content.replace('\n', "\n> > ");
+                out.push_str(&format!(
+                    "> ↳ **{}** · {}{edited}\n> > {content}\n\n",
+                    reply.username, reply.timestamp
+                ));
+            }
+        }
+        out
+    }
+
+    fn to_html(&self) -> String {
+        let title = escape_html(&self.title);
+        let mut out = format!(
+            "<!doctype html>\n<html>\n<head>\n<meta charset=\"UTF-8\">\n<title>{title}</title>\n</head>\n<body>\n<h1>{title}</h1>\n"
+        );
+        for message in &self.messages {
+            let flair = message
+                .flair
+                .as_ref()
+                .map(|flair| format!(" {}", escape_html(flair)))
+                .unwrap_or_default();
+            let edited = if message.edited {
+                " <i>(edited)</i>"
+            } else {
+                ""
+            };
+            out.push_str(&format!(
+                "<div>\n<b>{}</b>{flair} <small>{}</small>{edited}\n<p style=\"white-space: pre-wrap\">{}</p>\n",
+                escape_html(&message.username),
+                escape_html(&message.timestamp),
+                escape_html(&message.content)
+            ));
+            for reply in &message.replies {
+                let edited = if reply.edited { " <i>(edited)</i>" } else { "" };
+                out.push_str(&format!(
+                    "<div style=\"margin-left: 2em\">\n<b>{}</b> <small>{}</small>{edited}\n<p style=\"white-space: pre-wrap\">{}</p>\n</div>\n",
+                    escape_html(&reply.username),
+                    escape_html(&reply.timestamp),
+                    escape_html(&reply.content)
+                ));
+            }
+            out.push_str("</div>\n");
+        }
+        out.push_str("</body>\n</html>\n");
+        out
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Formats milliseconds since the epoch as `YYYY-MM-DD HH:MM UTC`, reports get shared across timezones
+pub fn format_timestamp(ts: f64) -> String {
+    let days = (ts / MS_PER_DAY).floor() as i64;
+    let ms_of_day = (ts - days as f64 * MS_PER_DAY) as i64;
+    let (hours, minutes) = (ms_of_day / 3_600_000, ms_of_day / 60_000 % 60);
+
+    // civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = (if z >= 0 { z } else { z - 146_096 }) / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = if month <= 2 {
+        yoe + era * 400 + 1
+    } else {
+        yoe + era * 400
+    };
+
+    format!("{year:04}-{month:02}-{day:02} {hours:02}:{minutes:02} UTC")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(username: &str, content: &str, replies: Vec<ExportedMessage>) -> ExportedMessage {
+        ExportedMessage {
+            id: "1".to_string(),
+            username: username.to_string(),
+            flair: None,
+            ts: 0.0,
+            timestamp: format_timestamp(0.0),
+            content: content.to_string(),
+            edited: false,
+            replies,
+        }
+    }
+
+    #[test]
+    fn timestamps_are_utc() {
+        assert_eq!(format_timestamp(0.0), "1970-01-01 00:00 UTC");
+        assert_eq!(format_timestamp(MS_PER_DAY - 1.0), "1970-01-01 23:59 UTC");
+        assert_eq!(format_timestamp(MS_PER_DAY), "1970-01-02 00:00 UTC");
+        assert_eq!(
+            format_timestamp(11_016.0 * MS_PER_DAY),
+            "2000-02-29 00:00 UTC"
+        );
+        assert_eq!(
+            format_timestamp(11_017.0 * MS_PER_DAY + 90.0 * 60_000.0),
+            "2000-03-01 01:30 UTC"
+        );
+    }
+
+    #[test]
+    fn timestamps_before_1970() {
+        assert_eq!(format_timestamp(-1.0), "1969-12-31 23:59 UTC");
+        assert_eq!(format_timestamp(-MS_PER_DAY), "1969-12-31 00:00 UTC");
+        assert_eq!(
+            format_timestamp(-365.0 * MS_PER_DAY),
+            "1969-01-01 00:00 UTC"
+        );
+        assert_eq!(
+            format_timestamp(-25_567.0 * MS_PER_DAY),
+            "1900-01-01 00:00 UTC"
+        );
+    }
+
+    #[test]
+    fn html_is_escaped() {
+        let transcript = Transcript {
+            title: "<general>".to_string(),
+            messages: vec![message(
+                "<script>",
+                "a < b && \"c\" > 'd'",
+                vec![message("bob", "</p>", vec![])],
+            )],
+        };
+        let html = transcript.render(ExportFormat::Html);
+        assert!(html.contains("<title>&lt;general&gt;</title>"));
+        assert!(html.contains("<b>&lt;script&gt;</b>"));
+        assert!(html.contains("a &lt; b &amp;&amp; &quot;c&quot; &gt; &#39;d&#39;"));
+        assert!(html.contains("&lt;/p&gt;"));
+        assert!(!html.contains("<script>"));
+    }
+
+    #[test]
+    fn markdown_quotes_every_line() {
+        let transcript = Transcript {
+            title: "general".to_string(),
+            messages: vec![message(
+                "alice",
+                "one\ntwo",
+                vec![message("bob", "three\nfour", vec![])],
+            )],
+        };
+        assert_eq!(
+            transcript.render(ExportFormat::Markdown),
+            "# general\n\n\
+             **alice** · 1970-01-01 00:00 UTC\n> one\n> two\n\n\
+             > ↳ **bob** · 1970-01-01 00:00 UTC\n> > three\n> > four\n\n"
+        );
+    }
+}
@@ -0,0 +1,507 @@
+//! Syntax highlighting for fenced code blocks, small hand-written scanners so nothing is fetched.
+
+/// What a run of code is, for picking its color
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TokenKind {
+    Keyword,
+    Type,
+    Str,
+    Number,
+    Comment,
+    Macro,
+    Attribute,
+    Lifetime,
+    // log levels
+    Error,
+    Warning,
+    Info,
+    Muted,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Language {
+    Rust,
+    Sql,
+    Log,
+}
+
+impl Language {
+    /// From the info string of a fence, e.g. ```` ```rust ````
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        match tag.to_lowercase().as_str() {
+            "rust" | "rs" => Some(Language::Rust),
+            "sql" | "postgres" | "postgresql" | "psql" | "mysql" | "sqlite" => Some(Language::Sql),
+            "log" | "logs" => Some(Language::Log),
+            _ => None,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Language::Rust => "Rust",
+            Language::Sql => "SQL",
+            Language::Log => "Log",
+        }
+    }
+}
+
+/// Runs of code and what they are, put back together they're the code exactly
+pub fn highlight_code(language: Language, code: &str) -> Vec<(String, Option<TokenKind>)> {
+    let mut scanner = Scanner {
+        rest: code,
+        runs: vec![],
+    };
+    match language {
+        Language::Rust => scanner.rust(),
+        Language::Sql => scanner.sql(),
+        Language::Log => scanner.log(),
+    }
+    scanner.runs
+}
+
+const RUST_KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "self", "Self", "static", "struct", "super", "trait", "true", "type",
+    "unsafe", "use", "where", "while", "yield",
+];
+
+const RUST_PRIMITIVES: &[&str] = &[
+    "bool", "char", "f32", "f64", "i8", "i16", "i32", "i64", "i128", "isize", "str", "u8", "u16",
+    "u32", "u64", "u128", "usize",
+];
+
+const SQL_KEYWORDS: &[&str] = &[
+    "add",
+    "all",
+    "alter",
+    "and",
+    "as",
+    "asc",
+    "begin",
+    "between",
+    "by",
+    "case",
+    "cascade",
+    "check",
+    "commit",
+    "constraint",
+    "create",
+    "cross",
+    "default",
+    "delete",
+    "desc",
+    "distinct",
+    "drop",
+    "else",
+    "end",
+    "exists",
+    "explain",
+    "false",
+    "foreign",
+    "from",
+    "full",
+    "group",
+    "having",
+    "if",
+    "in",
+    "index",
+    "inner",
+    "insert",
+    "into",
+    "is",
+    "join",
+    "key",
+    "left",
+    "like",
+    "limit",
+    "not",
+    "null",
+    "offset",
+    "on",
+    "or",
+    "order",
+    "outer",
+    "primary",
+    "references",
+    "returning",
+    "right",
+    "rollback",
+    "select",
+    "set",
+    "table",
+    "then",
+    "transaction",
+    "true",
+    "union",
+    "unique",
+    "update",
+    "using",
+    "values",
+    "view",
+    "when",
+    "where",
+    "with",
+];
+
+const SQL_TYPES: &[&str] = &[
+    "bigint",
+    "blob",
+    "boolean",
+    "bool",
+    "char",
+    "date",
+    "decimal",
+    "double",
+    "float",
+    "int",
+    "integer",
+    "interval",
+    "json",
+    "jsonb",
+    "numeric",
+    "real",
+    "serial",
+    "smallint",
+    "text",
+    "time",
+    "timestamp",
+    "timestamptz",
+    "uuid",
+    "varchar",
+];
+
+struct Scanner<'a> {
+    rest: &'a str,
+    runs: Vec<(String, Option<TokenKind>)>,
+}
+
+impl Scanner<'_> {
+    fn peek(&self) -> Option<char> {
+        self.rest.chars().next()
+    }
+
+    fn peek_second(&self) -> Option<char> {
+        self.rest.chars().nth(1)
+    }
+
+    /// Takes the next `len` bytes as one run, joining it onto the last run if that's the same kind
+    fn take(&mut self, len: usize, kind: Option<TokenKind>) {
+        let len = len.min(self.rest.len());
+        let (text, rest) = self.rest.split_at(len);
+        self.rest = rest;
+        match self.runs.last_mut() {
+            Some((last, last_kind)) if *last_kind == kind => last.push_str(text),
+            _ => self.runs.push((text.to_string(), kind)),
+        }
+    }
+
+    fn take_char(&mut self, kind: Option<TokenKind>) {
+        let len = self.peek().map_or(0, char::len_utf8);
+        self.take(len, kind);
+    }
+
+    /// Bytes up to the end of the line
+    fn line_len(&self) -> usize {
+        self.rest.find('\n').unwrap_or(self.rest.len())
+    }
+
+    fn word_len(&self) -> usize {
+        self.rest
+            .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .unwrap_or(self.rest.len())
+    }
+
+    /// `/* ... */`, which nest in Rust but not SQL
+    fn block_comment_len(&self, nested: bool) -> usize {
+        let mut depth = 0;
+        let mut i = 0;
+        while i < self.rest.len() {
+            let rest = &self.rest[i..];
+            if rest.starts_with("/*") && (nested || depth == 0) {
+                depth += 1;
+                i += 2;
+            } else if rest.starts_with("*/") {
+                depth -= 1;
+                i += 2;
+                if depth == 0 {
+                    return i;
+                }
+            } else {
+                i += rest.chars().next().map_or(1, char::len_utf8);
+            }
+        }
+        self.rest.len()
+    }
+
+    /// A string starting at `start` closed by `quote`, `doubled` quotes escape in SQL, backslashes in Rust
+    fn quoted_len(&self, start: usize, quote: char, doubled: bool) -> usize {
+        let mut chars = self.rest[start..].char_indices().skip(1);
+        while let Some((i, c)) = chars.next() {
+            if c == '\\' && !doubled {
+                chars.next();
+            } else if c == quote {
+                if doubled && self.rest[start + i + 1..].starts_with(quote) {
+                    chars.next();
+                    continue;
+                }
+                return start + i + c.len_utf8();
+            }
+        }
+        self.rest.len()
+    }
+
+    /// Digits, including `0x1f`, `1_000`, `2.5e3` and suffixes like `10u8`, but not the `..` of a range
+    fn number_len(&self) -> usize {
+        let mut len = 0;
+        let mut chars = self.rest.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            let is_decimal_point =
+                c == '.' && chars.peek().is_some_and(|(_, next)| next.is_ascii_digit());
+            if !(c.is_alphanumeric() || c == '_' || is_decimal_point) {
+                break;
+            }
+            len = i + c.len_utf8();
+        }
+        len
+    }
+
+    fn rust(&mut self) {
+        while let Some(c) = self.peek() {
+            let rest = self.rest;
+            if rest.starts_with("//") {
+                self.take(self.line_len(), Some(TokenKind::Comment));
+            } else if rest.starts_with("/*") {
+                self.take(self.block_comment_len(true), Some(TokenKind::Comment));
+            } else if rest.starts_with("#[") || rest.starts_with("#![") {
+                let len = rest.find(']').map_or(rest.len(), |end| end + 1);
+                self.take(len, Some(TokenKind::Attribute));
+            } else if let Some(len) = self.raw_string_len() {
+                self.take(len, Some(TokenKind::Str));
+            } else if c == '"' || (c == 'b' && self.peek_second() == Some('"')) {
+                let start = if c == 'b' { 1 } else { 0 };
+                self.take(self.quoted_len(start, '"', false), Some(TokenKind::Str));
+            } else if c == '\'' {
+                self.char_or_lifetime();
+            } else if c.is_ascii_digit() {
+                self.take(self.number_len(), Some(TokenKind::Number));
+            } else if c.is_alphabetic() || c == '_' {
+                let len = self.word_len();
+                let word = &rest[..len];
+                if rest[len..].starts_with('!') && !rest[len..].starts_with("!=") {
+                    self.take(len + 1, Some(TokenKind::Macro));
+                } else if RUST_KEYWORDS.contains(&word) {
+                    self.take(len, Some(TokenKind::Keyword));
+                } else if RUST_PRIMITIVES.contains(&word) || c.is_uppercase() {
+                    self.take(len, Some(TokenKind::Type));
+                } else {
+                    self.take(len, None);
+                }
+            } else {
+                self.take_char(None);
+            }
+        }
+    }
+
+    /// `r"..."`, `r#"..."#`, `br"..."` and so on
+    fn raw_string_len(&self) -> Option<usize> {
+        let prefix = if self.rest.starts_with("br") { 2 } else { 1 };
+        let after = self
+            .rest
+            .strip_prefix(if prefix == 2 { "br" } else { "r" })?;
+        let hashes = after.chars().take_while(|c| *c == '#').count();
+        if !after[hashes..].starts_with('"') {
+            return None;
+        }
+        let closing = format!("\"{}", "#".repeat(hashes));
+        let body_start = prefix + hashes + 1;
+        let len = self.rest[body_start..]
+            .find(&closing)
+            .map_or(self.rest.len(), |end| body_start + end + closing.len());
+        Some(len)
+    }
+
+    /// `'a'` and `'\n'` are chars, `'a` is a lifetime
+    fn char_or_lifetime(&mut self) {
+        let after = &self.rest[1..];
+        if after.starts_with('\\') {
+            self.take(self.quoted_len(0, '\'', false), Some(TokenKind::Str));
+            return;
+        }
+        let mut chars = after.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), Some('\'')) => self.take(1 + c.len_utf8() + 1, Some(TokenKind::Str)),
+            (Some(c), _) if c.is_alphabetic() || c == '_' => {
+                let len = 1 + after
+                    .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                    .unwrap_or(after.len());
+                self.take(len, Some(TokenKind::Lifetime));
+            }
+            _ => self.take_char(None),
+        }
+    }
+
+    fn sql(&mut self) {
+        while let Some(c) = self.peek() {
+            let rest = self.rest;
+            if rest.starts_with("--") {
+                self.take(self.line_len(), Some(TokenKind::Comment));
+            } else if rest.starts_with("/*") {
+                self.take(self.block_comment_len(false), Some(TokenKind::Comment));
+            } else if c == '\'' {
+                self.take(self.quoted_len(0, '\'', true), Some(TokenKind::Str));
+            } else if c.is_ascii_digit() {
+                self.take(self.number_len(), Some(TokenKind::Number));
+            } else if c.is_alphabetic() || c == '_' {
+                let len = self.word_len();
+                // keywords are case-insensitive, and people type them both ways
+                let word = rest[..len].to_lowercase();
+                let kind = if SQL_KEYWORDS.contains(&word.as_str()) {
+                    Some(TokenKind::Keyword)
+                } else if SQL_TYPES.contains(&word.as_str()) {
+                    Some(TokenKind::Type)
+                } else {
+                    None
+                };
+                self.take(len, kind);
+            } else {
+                self.take_char(None);
+            }
+        }
+    }
+
+    fn log(&mut self) {
+        while let Some(c) = self.peek() {
+            let rest = self.rest;
+            if c.is_ascii_digit() {
+                // timestamps, durations and counts
+                let len = rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || ":.-+_".contains(c)))
+                    .unwrap_or(rest.len());
+                self.take(len, Some(TokenKind::Number));
+            } else if c == '"' {
+                let len = self.quoted_len(0, '"', false).min(self.line_len());
+                self.take(len, Some(TokenKind::Str));
+            } else if c.is_alphabetic() {
+                let len = self.word_len();
+                let kind = match rest[..len].to_uppercase().as_str() {
+                    "ERROR" | "ERR" | "FATAL" | "PANIC" | "CRITICAL" => Some(TokenKind::Error),
+                    "WARN" | "WARNING" => Some(TokenKind::Warning),
+                    "INFO" | "NOTICE" => Some(TokenKind::Info),
+                    "DEBUG" | "TRACE" => Some(TokenKind::Muted),
+                    _ => None,
+                };
+                self.take(len, kind);
+            } else {
+                self.take_char(None);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The runs that got a kind, plain runs left out
+    fn tokens(language: Language, code: &str) -> Vec<(String, TokenKind)> {
+        highlight_code(language, code)
+            .into_iter()
+            .filter_map(|(text, kind)| kind.map(|kind| (text, kind)))
+            .collect()
+    }
+
+    fn token(text: &str, kind: TokenKind) -> (String, TokenKind) {
+        (text.to_string(), kind)
+    }
+
+    #[test]
+    fn runs_put_back_together_are_the_code() {
+        let samples = [
+            "fn main() {\n    let s = r#\"raw \"q\"\"#; // done\n}",
+            "let b = b\"bytes\\\"\"; é /* open",
+            "\"unterminated",
+            "'",
+            "r##\"never closed\"#",
+            "SELECT 'it''s' FROM t -- ünïcode\n/* a /* b */",
+            "2024-01-02T03:04:05Z ERROR \"unclosed\nnext",
+            "",
+        ];
+        for language in [Language::Rust, Language::Sql, Language::Log] {
+            for code in samples {
+                let runs = highlight_code(language, code);
+                let joined: String = runs.iter().map(|(text, _)| text.as_str()).collect();
+                assert_eq!(joined, code, "{language:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn rust_raw_strings() {
+        assert_eq!(
+            tokens(Language::Rust, r##"x = r#"a "quoted" b"#; y = br"c";"##),
+            vec![
+                token(r##"r#"a "quoted" b"#"##, TokenKind::Str),
+                token(r#"br"c""#, TokenKind::Str),
+            ]
+        );
+    }
+
+    #[test]
+    fn rust_lifetimes_and_chars() {
+        assert_eq!(
+            tokens(Language::Rust, r"<'a> 'a' '\n' 'static"),
+            vec![
+                token("'a", TokenKind::Lifetime),
+                token("'a'", TokenKind::Str),
+                token(r"'\n'", TokenKind::Str),
+                token("'static", TokenKind::Lifetime),
+            ]
+        );
+    }
+
+    #[test]
+    fn rust_block_comments_nest() {
+        assert_eq!(
+            highlight_code(Language::Rust, "/* a /* b */ c */ x"),
+            vec![
+                ("/* a /* b */ c */".to_string(), Some(TokenKind::Comment)),
+                (" x".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn sql_doubled_quotes_and_comments() {
+        assert_eq!(
+            tokens(
+                Language::Sql,
+                "select 'O''Brien' -- it's\nFROM t /* a /* b */ c"
+            ),
+            vec![
+                token("select", TokenKind::Keyword),
+                token("'O''Brien'", TokenKind::Str),
+                token("-- it's", TokenKind::Comment),
+                token("FROM", TokenKind::Keyword),
+                // block comments don't nest in SQL
+                token("/* a /* b */", TokenKind::Comment),
+            ]
+        );
+    }
+
+    #[test]
+    fn log_levels() {
+        assert_eq!(
+            tokens(
+                Language::Log,
+                "[ERROR] a\nwarn: b\nInfo c\nDEBUG d\nerrors e"
+            ),
+            vec![
+                token("ERROR", TokenKind::Error),
+                token("warn", TokenKind::Warning),
+                token("Info", TokenKind::Info),
+                token("DEBUG", TokenKind::Muted),
+            ]
+        );
+    }
+}
@@ -1,268 +1,2482 @@
-use std::cell::RefCell;
-use std::collections::HashMap;
+use crate::export::{format_timestamp, ExportedMessage, Transcript};
+use crate::search::{Query, SearchIndex};
+use crate::storage::{Drafts, NotifyLevel, Preferences, SidebarSection, SortOrder};
+use leptos::*;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::rc::Rc;
 use turtle_protocol::{
-    Channel, ChannelId, ChannelsInfo, ChatMessage, SendableId, User, UserId, UsersInfo,
+    Channel, ChannelArchived, ChannelDeleted, ChannelId, ChannelMembers, ChannelPins,
+    ChannelRenamed, ChannelTopicChanged, ChannelsInfo, ChatMessage, ChatMessageDeleted,
+    ChatMessageEdited, Group, GroupId, GroupsInfo, History, LookupResult, MemberJoined, MemberLeft,
+    MessagePinned, MessageUnpinned, Presence, PresenceChanged, ProfileUpdated, ReactionAdded,
+    ReactionRemoved, ReadMarkers, SendableId, ThreadReply, TypingStarted, TypingStopped, User,
+    UserId, UsersInfo,
 };
 
-#[derive(Clone, Debug)]
+/// How many timeline messages a mailbox keeps in memory, unless configured otherwise.
+/// The open one keeps more while scrolled back through its history.
+pub const DEFAULT_MAILBOX_CAPACITY: usize = 500;
+
+/// Search remembers this many times what each mailbox keeps in memory, oldest forgotten first
+const SEARCH_CAPACITY_FACTOR: usize = 4;
+
+/// How many older messages to ask the server for when scrolling back
+pub const HISTORY_PAGE_SIZE: u32 = 100;
+
+/// Most search results worth showing at once
+const SEARCH_RESULT_LIMIT: usize = 50;
+
+/// How long someone counts as typing after their last typing event, in milliseconds
+const TYPING_TIMEOUT_MS: f64 = 6000.0;
+
+/// Most users offered when completing an `@mention`
+const MENTION_SUGGESTION_LIMIT: usize = 8;
+
+/// Emoji reactions on a single message, in the order they were first added
+pub type Reactions = Vec<(String, Vec<UserId>)>;
+
+/// Panels that can be open on the right, next to the chat
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SidePanel {
+    Saved,
+    Settings,
+}
+
+/// Ids we've come across without knowing who or what they are
+#[derive(Clone, Debug, Default)]
+pub struct Unresolved {
+    pub users: HashSet<UserId>,
+    pub channels: HashSet<ChannelId>,
+    pub groups: HashSet<GroupId>,
+}
+
+impl Unresolved {
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty() && self.channels.is_empty() && self.groups.is_empty()
+    }
+}
+
+/// Setting a signal notifies even if nothing changed, flags get set over and over
+fn set_if_changed<T: PartialEq + 'static>(signal: RwSignal<T>, value: T) {
+    if signal.with_untracked(|current| *current != value) {
+        signal.set(value);
+    }
+}
+
+/// Each preference on its own, so changing one only re-runs the views that read it
+#[derive(Clone, Copy)]
+struct PreferenceSlices {
+    bookmarks: Memo<Vec<(SendableId, String)>>,
+    categories: Memo<HashMap<ChannelId, String>>,
+    collapsed: Memo<HashSet<String>>,
+    desktop_notifications: Memo<bool>,
+    ignored: Memo<HashSet<UserId>>,
+    mailbox_capacity: Memo<Option<usize>>,
+    manual_orders: Memo<HashMap<SidebarSection, Vec<SendableId>>>,
+    nicknames: Memo<HashMap<UserId, String>>,
+    notify: Memo<HashMap<SendableId, NotifyLevel>>,
+    plain_text: Memo<bool>,
+    sort_order: Memo<SortOrder>,
+    starred: Memo<HashSet<SendableId>>,
+}
+
+impl PreferenceSlices {
+    fn new(preferences: RwSignal<Preferences>) -> Self {
+        fn slice<T: Clone + PartialEq + 'static>(
+            preferences: RwSignal<Preferences>,
+            field: fn(&Preferences) -> &T,
+        ) -> Memo<T> {
+            create_memo(move |_| preferences.with(|preferences| field(preferences).clone()))
+        }
+        Self {
+            bookmarks: slice(preferences, |p| &p.bookmarks),
+            categories: slice(preferences, |p| &p.categories),
+            collapsed: slice(preferences, |p| &p.collapsed),
+            desktop_notifications: slice(preferences, |p| &p.desktop_notifications),
+            ignored: slice(preferences, |p| &p.ignored),
+            mailbox_capacity: slice(preferences, |p| &p.mailbox_capacity),
+            manual_orders: slice(preferences, |p| &p.manual_orders),
+            nicknames: slice(preferences, |p| &p.nicknames),
+            notify: slice(preferences, |p| &p.notify),
+            plain_text: slice(preferences, |p| &p.plain_text),
+            sort_order: slice(preferences, |p| &p.sort_order),
+            starred: slice(preferences, |p| &p.starred),
+        }
+    }
+}
+
+/// Every piece of a mailbox is its own signal, so views only re-run for the parts they read.
+/// Getters track, mutators read untracked.
+#[derive(Clone, Copy)]
 struct Mailbox {
-    display_name: Rc<RefCell<String>>,
-    has_unread: Rc<RefCell<bool>>,
-    is_active: Rc<RefCell<bool>>,
-    messages: Rc<RefCell<Vec<ChatMessage>>>,
+    display_name: RwSignal<String>,
+    has_unread: RwSignal<bool>,
+    has_mention: RwSignal<bool>,
+    is_active: RwSignal<bool>,
+    messages: RwSignal<VecDeque<Rc<ChatMessage>>>,
+    edited: RwSignal<HashSet<String>>,
+    deleted: RwSignal<HashSet<String>>,
+    reactions: RwSignal<HashMap<String, Reactions>>,
+    threads: RwSignal<HashMap<String, Vec<Rc<ChatMessage>>>>,
+    typing: RwSignal<HashMap<UserId, f64>>,
+    archived: RwSignal<bool>,
+    topic: RwSignal<String>,
+    description: RwSignal<String>,
+    divider_after: RwSignal<Option<String>>,
+    pins: RwSignal<Vec<String>>,
+    draft: RwSignal<String>,
+    category: RwSignal<Option<String>>,
+    /// What happened here that isn't a message, like topic changes, by the message it came after.
+    /// They never get message ids, so nothing about them reaches the server.
+    notices: RwSignal<Vec<(Option<String>, String)>>,
+    // bookkeeping nothing renders
+    last_read: StoredValue<Option<String>>,
+    loading_history: StoredValue<bool>,
+    history_exhausted: StoredValue<bool>,
+}
+
+impl Mailbox {
+    fn new(display_name: String) -> Self {
+        Self {
+            display_name: create_rw_signal(display_name),
+            has_unread: create_rw_signal(false),
+            has_mention: create_rw_signal(false),
+            is_active: create_rw_signal(false),
+            messages: create_rw_signal(VecDeque::new()),
+            edited: create_rw_signal(HashSet::new()),
+            deleted: create_rw_signal(HashSet::new()),
+            reactions: create_rw_signal(HashMap::new()),
+            threads: create_rw_signal(HashMap::new()),
+            typing: create_rw_signal(HashMap::new()),
+            archived: create_rw_signal(false),
+            topic: create_rw_signal(String::new()),
+            description: create_rw_signal(String::new()),
+            divider_after: create_rw_signal(None),
+            pins: create_rw_signal(vec![]),
+            draft: create_rw_signal(String::new()),
+            category: create_rw_signal(None),
+            notices: create_rw_signal(vec![]),
+            last_read: store_value(None),
+            loading_history: store_value(false),
+            history_exhausted: store_value(false),
+        }
+    }
+
+    fn get_display_name(&self) -> String {
+        self.display_name.get()
+    }
+
+    fn set_display_name(&self, display_name: String) {
+        set_if_changed(self.display_name, display_name);
+    }
+
+    fn set_archived(&self, archived: bool) {
+        set_if_changed(self.archived, archived);
+    }
+
+    fn is_archived(&self) -> bool {
+        self.archived.get()
+    }
+
+    fn set_topic(&self, topic: String, description: String) {
+        set_if_changed(self.topic, topic);
+        set_if_changed(self.description, description);
+    }
+
+    fn get_topic(&self) -> (String, String) {
+        (self.topic.get(), self.description.get())
+    }
+
+    fn set_category(&self, category: Option<String>) {
+        set_if_changed(self.category, category);
+    }
+
+    fn get_category(&self) -> Option<String> {
+        self.category.get()
+    }
+
+    fn get_draft(&self) -> String {
+        self.draft.get()
+    }
+
+    fn has_draft(&self) -> bool {
+        self.draft.with(|draft| !draft.trim().is_empty())
+    }
+
+    fn set_draft(&self, draft: String) {
+        set_if_changed(self.draft, draft);
+    }
+
+    fn add_message(&self, msg: ChatMessage, counts_as_unread: bool) {
+        // they're done typing if the message landed
+        if self
+            .typing
+            .with_untracked(|typing| typing.contains_key(&msg.from))
+        {
+            self.typing.update(|typing| {
+                typing.remove(&msg.from);
+            });
+        }
+        // history comes in order, so everything up to the read marker has been read
+        let is_last_read = self
+            .last_read
+            .with_value(|last_read| last_read.as_ref() == Some(&msg.id));
+        self.messages
+            .update(|messages| messages.push_back(Rc::new(msg)));
+        if is_last_read {
+            set_if_changed(self.has_unread, false);
+        } else if counts_as_unread && !self.is_active.get_untracked() {
+            set_if_changed(self.has_unread, true);
+        } // else has_unread = false ?
+    }
+
+    /// Puts older messages in front of the timeline, skipping any we already have
+    fn prepend_history(&self, older: Vec<ChatMessage>) {
+        self.loading_history.set_value(false);
+        if older.is_empty() {
+            self.history_exhausted.set_value(true);
+            return;
+        }
+        self.messages.update(|messages| {
+            let known: HashSet<String> = messages.iter().map(|msg| msg.id.clone()).collect();
+            for msg in older.into_iter().rev() {
+                if !known.contains(&msg.id) {
+                    messages.push_front(Rc::new(msg));
+                }
+            }
+        });
+    }
+
+    /// Whether the server may have messages from before `ts` that aren't loaded, or any older for `None`
+    fn is_missing_before(&self, ts: Option<f64>) -> bool {
+        if self.history_exhausted.get_value() {
+            return false;
+        }
+        self.messages.with(|messages| {
+            messages
+                .front()
+                .is_some_and(|oldest| ts.map(|ts| oldest.ts > ts).unwrap_or(true))
+        })
+    }
+
+    /// The oldest message to fetch history before, unless we're already waiting or there's none left
+    fn start_loading_history(&self) -> Option<String> {
+        if self.loading_history.get_value() || self.history_exhausted.get_value() {
+            return None;
+        }
+        let oldest_id = self
+            .messages
+            .with_untracked(|messages| messages.front().map(|msg| msg.id.clone()))?;
+        self.loading_history.set_value(true);
+        Some(oldest_id)
+    }
+
+    /// Drops the oldest messages past `capacity`, returns the ids of everything dropped
+    fn evict(&self, capacity: usize) -> Vec<String> {
+        if self
+            .messages
+            .with_untracked(|messages| messages.len() <= capacity)
+        {
+            return vec![];
+        }
+        let mut evicted = vec![];
+        self.messages.update(|messages| {
+            while messages.len() > capacity {
+                let Some(msg) = messages.pop_front() else {
+                    break;
+                };
+                evicted.push(msg.id.clone());
+            }
+        });
+        // replies go with their parent
+        self.threads.update(|threads| {
+            let replies: Vec<String> = evicted
+                .iter()
+                .filter_map(|id| threads.remove(id))
+                .flatten()
+                .map(|reply| reply.id.clone())
+                .collect();
+            evicted.extend(replies);
+        });
+        self.edited
+            .update(|edited| edited.retain(|id| !evicted.contains(id)));
+        self.deleted
+            .update(|deleted| deleted.retain(|id| !evicted.contains(id)));
+        self.reactions
+            .update(|reactions| reactions.retain(|id, _| !evicted.contains(id)));
+        // notices from before everything that's left go too
+        self.notices.update(|notices| {
+            notices.retain(|(after, _)| after.as_ref().is_some_and(|id| !evicted.contains(id)))
+        });
+        // there's more on the server now
+        self.history_exhausted.set_value(false);
+        evicted
+    }
+
+    fn add_notice(&self, notice: String) {
+        let after = self
+            .messages
+            .with_untracked(|messages| messages.back().map(|msg| msg.id.clone()));
+        self.notices.update(|notices| notices.push((after, notice)));
+    }
+
+    fn get_notices_after(&self, message_id: Option<&str>) -> Vec<String> {
+        self.notices.with(|notices| {
+            notices
+                .iter()
+                .filter(|(after, _)| after.as_deref() == message_id)
+                .map(|(_, notice)| notice.clone())
+                .collect()
+        })
+    }
+
+    fn last_activity(&self) -> f64 {
+        self.messages
+            .with(|messages| messages.back().map(|msg| msg.ts).unwrap_or(0.0))
+    }
+
+    fn last_message_id(&self) -> Option<String> {
+        self.messages
+            .with(|messages| messages.back().map(|msg| msg.id.clone()))
+    }
+
+    fn apply_read_marker(&self, message_id: String) {
+        let (position, last_message_id) = self.messages.with_untracked(|messages| {
+            (
+                messages.iter().position(|msg| msg.id == message_id),
+                messages.back().map(|msg| msg.id.clone()),
+            )
+        });
+        if position.is_some() {
+            // anything after the marker is unread
+            let has_unread = last_message_id.as_ref() != Some(&message_id);
+            set_if_changed(self.has_unread, has_unread);
+            if !has_unread {
+                set_if_changed(self.has_mention, false);
+            }
+        }
+        let no_divider = self
+            .divider_after
+            .with_untracked(|divider| divider.is_none());
+        if self.is_active.get_untracked() && no_divider && position.is_some() {
+            self.set_divider(&message_id, last_message_id.as_deref());
+        }
+        self.last_read.set_value(Some(message_id));
+    }
+
+    /// Records the newest message as read, returns its id if that's news.
+    /// Tracks the timeline, so an effect calling this re-runs as messages come in.
+    fn mark_read(&self) -> Option<String> {
+        let last_message_id = self.last_message_id()?;
+        if self
+            .last_read
+            .with_value(|last_read| last_read.as_ref() == Some(&last_message_id))
+        {
+            return None;
+        }
+        self.last_read.set_value(Some(last_message_id.clone()));
+        Some(last_message_id)
+    }
+
+    // only worth a divider if something came in after the marker
+    fn set_divider(&self, last_read: &str, last_message_id: Option<&str>) {
+        let divider = (last_message_id != Some(last_read)).then(|| last_read.to_string());
+        self.divider_after.set(divider);
+    }
+
+    fn get_divider(&self) -> Option<String> {
+        self.divider_after.get()
+    }
+
+    fn set_typing(&self, user_id: UserId, expires_at: f64) {
+        self.typing.update(|typing| {
+            typing.insert(user_id, expires_at);
+        });
+    }
+
+    fn clear_typing(&self, user_id: UserId) {
+        if self
+            .typing
+            .with_untracked(|typing| typing.contains_key(&user_id))
+        {
+            self.typing.update(|typing| {
+                typing.remove(&user_id);
+            });
+        }
+    }
+
+    /// Drops typing entries that have expired, only touching the signal if any did
+    fn prune_typing(&self, now: f64) {
+        let expired = self
+            .typing
+            .with_untracked(|typing| typing.values().any(|expires_at| *expires_at <= now));
+        if expired {
+            self.typing
+                .update(|typing| typing.retain(|_, expires_at| *expires_at > now));
+        }
+    }
+
+    fn get_typing(&self) -> Vec<UserId> {
+        self.typing.with(|typing| typing.keys().copied().collect())
+    }
+
+    fn add_thread_reply(&self, parent_id: String, msg: ChatMessage, counts_as_unread: bool) {
+        self.threads.update(|threads| {
+            threads.entry(parent_id).or_default().push(Rc::new(msg));
+        });
+        if counts_as_unread && !self.is_active.get_untracked() {
+            set_if_changed(self.has_unread, true);
+        }
+    }
+
+    // looks in the main timeline first, then in the threads, returns whether it was found
+    fn with_message_mut(&self, id: &str, f: impl FnOnce(&mut ChatMessage)) -> bool {
+        let in_timeline = self
+            .messages
+            .with_untracked(|messages| messages.iter().any(|msg| msg.id == id));
+        let in_thread = !in_timeline
+            && self
+                .threads
+                .with_untracked(|threads| threads.values().flatten().any(|msg| msg.id == id));
+        if in_timeline {
+            self.messages.update(|messages| {
+                if let Some(msg) = messages.iter_mut().find(|msg| msg.id == id) {
+                    f(Rc::make_mut(msg));
+                }
+            });
+        } else if in_thread {
+            self.threads.update(|threads| {
+                if let Some(msg) = threads.values_mut().flatten().find(|msg| msg.id == id) {
+                    f(Rc::make_mut(msg));
+                }
+            });
+        }
+        in_timeline || in_thread
+    }
+
+    fn get_message(&self, id: &str) -> Option<ChatMessage> {
+        let found = self.messages.with(|messages| {
+            let msg = messages.iter().find(|msg| msg.id == id);
+            msg.map(|msg| ChatMessage::clone(msg))
+        });
+        found.or_else(|| {
+            self.threads.with(|threads| {
+                let msg = threads.values().flatten().find(|msg| msg.id == id);
+                msg.map(|msg| ChatMessage::clone(msg))
+            })
+        })
+    }
+
+    /// Only edits and deletes change what a message says, so this tracks those rather than
+    /// the whole timeline, new messages don't re-run every row
+    fn get_content(&self, id: &str) -> Option<String> {
+        self.edited.track();
+        self.deleted.track();
+        let found = self.messages.with_untracked(|messages| {
+            let msg = messages.iter().find(|msg| msg.id == id);
+            msg.map(|msg| msg.content.clone())
+        });
+        found.or_else(|| {
+            self.threads.with_untracked(|threads| {
+                let msg = threads.values().flatten().find(|msg| msg.id == id);
+                msg.map(|msg| msg.content.clone())
+            })
+        })
+    }
+
+    fn set_pins(&self, message_ids: Vec<String>) {
+        self.pins.set(message_ids);
+    }
+
+    fn pin(&self, message_id: String) {
+        if !self.pins.with_untracked(|pins| pins.contains(&message_id)) {
+            self.pins.update(|pins| pins.push(message_id));
+        }
+    }
+
+    fn unpin(&self, message_id: &str) {
+        self.pins.update(|pins| pins.retain(|id| id != message_id));
+    }
+
+    fn is_pinned(&self, message_id: &str) -> bool {
+        self.pins
+            .with(|pins| pins.iter().any(|id| id == message_id))
+    }
+
+    fn get_pins(&self) -> Vec<String> {
+        self.pins.get()
+    }
+
+    fn thread_parent(&self, id: &str) -> Option<String> {
+        self.threads.with(|threads| {
+            threads
+                .iter()
+                .find(|(_, replies)| replies.iter().any(|msg| msg.id == id))
+                .map(|(parent_id, _)| parent_id.clone())
+        })
+    }
+
+    fn edit_message(&self, id: &str, content: String) {
+        if self.with_message_mut(id, |msg| msg.content = content) {
+            self.edited.update(|edited| {
+                edited.insert(id.to_string());
+            });
+        }
+    }
+
+    fn delete_message(&self, id: &str) {
+        // keep the message around as a tombstone so the timeline doesn't jump
+        if self.with_message_mut(id, |msg| msg.content.clear()) {
+            self.deleted.update(|deleted| {
+                deleted.insert(id.to_string());
+            });
+        }
+    }
+
+    fn is_edited(&self, id: &str) -> bool {
+        self.edited.with(|edited| edited.contains(id))
+    }
+
+    fn is_deleted(&self, id: &str) -> bool {
+        self.deleted.with(|deleted| deleted.contains(id))
+    }
+
+    fn add_reaction(&self, message_id: &str, emoji: String, user_id: UserId) {
+        self.reactions.update(|reactions| {
+            let message_reactions = reactions.entry(message_id.to_string()).or_default();
+            match message_reactions.iter_mut().find(|(e, _)| *e == emoji) {
+                Some((_, user_ids)) if !user_ids.contains(&user_id) => user_ids.push(user_id),
+                Some(_) => {} // already reacted
+                None => message_reactions.push((emoji, vec![user_id])),
+            }
+        });
+    }
+
+    fn remove_reaction(&self, message_id: &str, emoji: &str, user_id: UserId) {
+        self.reactions.update(|reactions| {
+            if let Some(message_reactions) = reactions.get_mut(message_id) {
+                for (_, user_ids) in message_reactions.iter_mut().filter(|(e, _)| e == emoji) {
+                    user_ids.retain(|uid| *uid != user_id);
+                }
+                // drop emojis nobody is reacting with anymore
+                message_reactions.retain(|(_, user_ids)| !user_ids.is_empty());
+            }
+        });
+    }
+
+    fn get_reactions(&self, message_id: &str) -> Reactions {
+        self.reactions
+            .with(|reactions| reactions.get(message_id).cloned().unwrap_or_default())
+    }
+
+    fn set_active(&self) {
+        self.is_active.set(true);
+        set_if_changed(self.has_unread, false);
+        set_if_changed(self.has_mention, false);
+        // remember where we left off before it gets marked read
+        match self.last_read.get_value() {
+            Some(last_read) => {
+                let last_message_id = self
+                    .messages
+                    .with_untracked(|messages| messages.back().map(|msg| msg.id.clone()));
+                self.set_divider(&last_read, last_message_id.as_deref());
+            }
+            None => self.divider_after.set(None),
+        }
+    }
+
+    fn set_inactive(&self) {
+        self.is_active.set(false);
+    }
+
+    fn is_active(&self) -> bool {
+        self.is_active.get()
+    }
+
+    fn has_unread(&self) -> bool {
+        self.has_unread.get()
+    }
+
+    fn flag_mention(&self) {
+        if !self.is_active.get_untracked() {
+            set_if_changed(self.has_mention, true);
+        }
+    }
+
+    fn has_mention(&self) -> bool {
+        self.has_mention.get()
+    }
+
+    /// Cheap, the messages themselves are shared rather than copied
+    fn get_messages(&self) -> Vec<Rc<ChatMessage>> {
+        self.messages
+            .with(|messages| messages.iter().cloned().collect())
+    }
+
+    fn get_thread_replies(&self, parent_id: &str) -> Vec<Rc<ChatMessage>> {
+        self.threads
+            .with(|threads| threads.get(parent_id).cloned().unwrap_or_default())
+    }
+
+    fn reply_count(&self, parent_id: &str) -> usize {
+        self.threads.with(|threads| {
+            threads
+                .get(parent_id)
+                .map(|replies| replies.len())
+                .unwrap_or(0)
+        })
+    }
+}
+
+/// All client state, as signals. It's `Copy`, so it goes straight into context.
+/// Whatever reads a getter inside a view or effect only re-runs when that piece changes,
+/// a message in #random doesn't touch anything #general's sidebar entry reads.
+#[derive(Clone, Copy)]
+pub struct Mailroom {
+    active_id: RwSignal<SendableId>,
+    active_hook: StoredValue<Option<Box<dyn FnMut() + 'static>>>,
+    active_thread: RwSignal<Option<String>>,
+    /// Whether the newest message in the active mailbox is in view.
+    /// Nobody's looking at older ones then, so it's trimmed like the rest.
+    at_bottom: StoredValue<bool>,
+    browsing: RwSignal<bool>,
+    channel_members: RwSignal<HashMap<ChannelId, HashSet<UserId>>>,
+    current_user_id: RwSignal<Option<UserId>>,
+    /// Saved drafts, mailboxes pick theirs up when they're made
+    drafts: StoredValue<Drafts>,
+    group_members: RwSignal<HashMap<GroupId, Vec<UserId>>>,
+    highlighted: RwSignal<Option<String>>,
+    /// Already asked the server about these, no need to ask again
+    looked_up: StoredValue<Unresolved>,
+    /// A mailbox handle never changes, its pieces are signals.
+    /// So readers only need to hear when one comes or goes.
+    mailbox_keys: Trigger,
+    mailboxes: StoredValue<HashMap<SendableId, Mailbox>>,
+    /// Whether we're in each channel, as far as the server has said.
+    /// Membership comes with ChannelMembers, until then it's unknown and the channel isn't joined.
+    membership: RwSignal<HashMap<ChannelId, bool>>,
+    message_index: StoredValue<HashMap<String, SendableId>>,
+    /// Replies that arrived before their parent, by parent id
+    orphan_replies: StoredValue<HashMap<String, Vec<ThreadReply>>>,
+    /// Mailboxes are mostly created from websocket handlers, which run outside any owner
+    owner: Owner,
+    preferences: RwSignal<Preferences>,
+    presence: RwSignal<HashMap<UserId, Presence>>,
+    search_index: StoredValue<SearchIndex>,
+    side_panel: RwSignal<Option<SidePanel>>,
+    slices: PreferenceSlices,
+    unresolved: RwSignal<Unresolved>,
+    users: RwSignal<HashMap<UserId, User>>,
 }
 
-impl Mailbox {
-    fn new(display_name: String) -> Self {
-        Self {
-            display_name: Rc::new(RefCell::new(display_name)),
-            has_unread: Rc::new(RefCell::new(false)),
-            is_active: Rc::new(RefCell::new(false)),
-            messages: Rc::new(RefCell::new(vec![])),
+impl Mailroom {
+    /// Has to be called inside a component, the signals belong to it.
+    /// Panics otherwise, there'd be no owner to make mailboxes under later.
+    pub fn new(active_id: impl Into<SendableId>) -> Self {
+        let preferences = create_rw_signal(Preferences::default());
+        Self {
+            active_id: create_rw_signal(active_id.into()),
+            active_hook: store_value(None),
+            active_thread: create_rw_signal(None),
+            at_bottom: store_value(true),
+            browsing: create_rw_signal(false),
+            channel_members: create_rw_signal(HashMap::new()),
+            current_user_id: create_rw_signal(None),
+            drafts: store_value(Drafts::default()),
+            group_members: create_rw_signal(HashMap::new()),
+            highlighted: create_rw_signal(None),
+            looked_up: store_value(Unresolved::default()),
+            mailbox_keys: create_trigger(),
+            mailboxes: store_value(HashMap::new()),
+            membership: create_rw_signal(HashMap::new()),
+            message_index: store_value(HashMap::new()),
+            orphan_replies: store_value(HashMap::new()),
+            owner: Owner::current().expect("the mailroom is created inside a component"),
+            preferences,
+            presence: create_rw_signal(HashMap::new()),
+            search_index: store_value(SearchIndex::default()),
+            side_panel: create_rw_signal(None),
+            slices: PreferenceSlices::new(preferences),
+            unresolved: create_rw_signal(Unresolved::default()),
+            users: create_rw_signal(HashMap::new()),
+        }
+    }
+
+    /// Only a missing mailbox is tracked, the lookup re-runs when it's made
+    fn mailbox(&self, id: SendableId) -> Option<Mailbox> {
+        let mailbox = self
+            .mailboxes
+            .with_value(|mailboxes| mailboxes.get(&id).copied());
+        if mailbox.is_none() {
+            self.mailbox_keys.track();
+        }
+        mailbox
+    }
+
+    /// Finds the mailbox, making it first if it's new
+    fn mailbox_or_insert(&self, id: SendableId, display_name: impl FnOnce() -> String) -> Mailbox {
+        if let Some(mailbox) = self.mailbox(id) {
+            return mailbox;
+        }
+        let mailbox = with_owner(self.owner, || Mailbox::new(display_name()));
+        if let Some(draft) = self.drafts.with_value(|drafts| drafts.0.get(&id).cloned()) {
+            mailbox.set_draft(draft);
+        }
+        self.mailboxes.update_value(|mailboxes| {
+            mailboxes.insert(id, mailbox);
+        });
+        self.mailbox_keys.notify();
+        mailbox
+    }
+
+    pub fn set_current_user_id(&self, user_id: UserId) {
+        self.current_user_id.set(Some(user_id));
+        // preferences are per account
+        self.preferences.set(Preferences::load(user_id));
+        // and so are drafts
+        let drafts = Drafts::load(user_id);
+        let mailboxes: Vec<_> = self
+            .mailboxes
+            .with_value(|mailboxes| mailboxes.iter().map(|(id, mb)| (*id, *mb)).collect());
+        for (id, mailbox) in mailboxes {
+            mailbox.set_draft(drafts.0.get(&id).cloned().unwrap_or_default());
+        }
+        self.drafts.set_value(drafts);
+    }
+
+    /// The unsent message for a mailbox
+    pub fn draft(&self, id: impl Into<SendableId>) -> String {
+        self.mailbox(id.into())
+            .map(|mb| mb.get_draft())
+            .unwrap_or_default()
+    }
+
+    pub fn has_draft(&self, id: impl Into<SendableId>) -> bool {
+        self.mailbox(id.into())
+            .map(|mb| mb.has_draft())
+            .unwrap_or(false)
+    }
+
+    pub fn set_draft(&self, id: impl Into<SendableId>, draft: String) {
+        let mailbox_id = id.into();
+        let Some(mailbox) = self.mailbox(mailbox_id) else {
+            return;
+        };
+        self.drafts.update_value(|drafts| {
+            if draft.is_empty() {
+                drafts.0.remove(&mailbox_id);
+            } else {
+                drafts.0.insert(mailbox_id, draft.clone());
+            }
+        });
+        mailbox.set_draft(draft);
+        if let Some(user_id) = self.current_user_id.get_untracked() {
+            self.drafts.with_value(|drafts| drafts.save(user_id));
+        }
+    }
+
+    fn save_preferences(&self) {
+        if let Some(user_id) = self.current_user_id.get_untracked() {
+            self.preferences
+                .with_untracked(|preferences| preferences.save(user_id));
+        }
+    }
+
+    pub fn notify_level(&self, id: impl Into<SendableId>) -> NotifyLevel {
+        let mailbox_id = id.into();
+        self.slices
+            .notify
+            .with(|notify| notify.get(&mailbox_id).copied().unwrap_or_default())
+    }
+
+    pub fn set_notify_level(&self, id: impl Into<SendableId>, level: NotifyLevel) {
+        let mailbox_id = id.into();
+        self.preferences.update(|preferences| {
+            if level == NotifyLevel::default() {
+                preferences.notify.remove(&mailbox_id);
+            } else {
+                preferences.notify.insert(mailbox_id, level);
+            }
+        });
+        self.save_preferences();
+    }
+
+    /// Whether the message has an `@username` for the current user in it
+    fn mentions_me(&self, content: &str) -> bool {
+        let Some(me) = self.current_user_id().and_then(|id| self.get_user(id)) else {
+            return false;
+        };
+        let mention = format!("@{}", me.username.to_lowercase());
+        content.split_whitespace().any(|word| {
+            word.to_lowercase()
+                .trim_end_matches(|c: char| !c.is_alphanumeric())
+                == mention
+        })
+    }
+
+    /// Title and body for a desktop notification, if this message deserves one
+    pub fn notification_for(&self, msg: &ChatMessage, focused: bool) -> Option<(String, String)> {
+        if !self.desktop_notifications() {
+            return None;
+        }
+        if Some(msg.from) == self.current_user_id() || self.is_ignored(msg.from) {
+            return None;
+        }
+        let mailbox_id = self.route(msg.from, msg.to);
+        if focused && self.is_active(mailbox_id) {
+            return None;
+        }
+        let wanted = match self.notify_level(mailbox_id) {
+            NotifyLevel::All => true,
+            NotifyLevel::Mentions => self.mentions_me(&msg.content),
+            NotifyLevel::Muted => false,
+        };
+        if !wanted {
+            return None;
+        }
+        let title = self.display_name(mailbox_id)?;
+        let username = self
+            .user_display_name(msg.from)
+            .unwrap_or("unknown user".to_string());
+        Some((title, format!("{username}: {}", msg.content)))
+    }
+
+    pub fn current_user_id(&self) -> Option<UserId> {
+        self.current_user_id.get()
+    }
+
+    pub fn is_ignored(&self, user_id: UserId) -> bool {
+        self.slices
+            .ignored
+            .with(|ignored| ignored.contains(&user_id))
+    }
+
+    /// Ignored users' messages still arrive, they're just collapsed and never count as unread
+    pub fn toggle_ignored(&self, user_id: UserId) {
+        self.preferences.update(|preferences| {
+            let ignored = &mut preferences.ignored;
+            if !ignored.remove(&user_id) {
+                ignored.insert(user_id);
+            }
+        });
+        self.save_preferences();
+    }
+
+    /// Everyone on the ignore list, by username
+    pub fn ignored_list(&self) -> Vec<(UserId, String)> {
+        let ignored = self.slices.ignored.get();
+        let mut list: Vec<_> = ignored
+            .into_iter()
+            .map(|uid| {
+                let username = self
+                    .user_display_name(uid)
+                    .unwrap_or("unknown user".to_string());
+                (uid, username)
+            })
+            .collect();
+        list.sort_by(|a, b| a.1.cmp(&b.1));
+        list
+    }
+
+    pub fn nickname(&self, user_id: UserId) -> Option<String> {
+        self.slices
+            .nicknames
+            .with(|nicknames| nicknames.get(&user_id).cloned())
+    }
+
+    /// A blank nickname clears it
+    pub fn set_nickname(&self, user_id: UserId, nickname: &str) {
+        let nickname = nickname.trim().to_string();
+        self.preferences.update(|preferences| {
+            if nickname.is_empty() {
+                preferences.nicknames.remove(&user_id);
+            } else {
+                preferences.nicknames.insert(user_id, nickname);
+            }
+        });
+        self.save_preferences();
+    }
+
+    /// What to call a user: our nickname for them, else the name they picked, else their username
+    pub fn user_display_name(&self, user_id: UserId) -> Option<String> {
+        let user = self.get_user(user_id);
+        self.nickname(user_id)
+            .or_else(|| {
+                user.as_ref()
+                    .and_then(|user| user.display_name.clone())
+                    .filter(|name| !name.trim().is_empty())
+            })
+            .or_else(|| user.map(|user| user.username))
+    }
+
+    /// The real username, when a nickname or display name is shown instead
+    pub fn hidden_username(&self, user_id: UserId) -> Option<String> {
+        let user = self.get_user(user_id)?;
+        let shown = self.user_display_name(user_id)?;
+        (shown != user.username).then_some(user.username)
+    }
+
+    pub fn avatar(&self, user_id: UserId) -> Option<String> {
+        self.get_user(user_id)
+            .and_then(|user| user.avatar)
+            .filter(|avatar| !avatar.is_empty())
+    }
+
+    /// Someone changed their profile, everything showing them picks it up
+    pub fn update_profile(&self, updated: ProfileUpdated) {
+        self.users.update(|users| {
+            if let Some(user) = users.get_mut(&updated.id) {
+                user.display_name = updated.display_name;
+                user.flair = updated.flair;
+                user.avatar = updated.avatar;
+            }
+        });
+    }
+
+    fn find_user_by_username(&self, username: &str) -> Option<UserId> {
+        let username = username.to_lowercase();
+        self.users.with(|users| {
+            users
+                .iter()
+                .find(|(_, user)| user.username.to_lowercase() == username)
+                .map(|(uid, _)| *uid)
+        })
+    }
+
+    /// Splits message text into plain runs and `@username` mentions of users we know
+    pub fn split_mentions(&self, content: &str) -> Vec<(String, Option<UserId>)> {
+        let is_username_char = |c: char| c.is_alphanumeric() || c == '_' || c == '-';
+        let mut segments = vec![];
+        let mut plain_start = 0;
+        let mut search_from = 0;
+        while let Some(found) = content[search_from..].find('@') {
+            let at = search_from + found;
+            let name_start = at + 1;
+            let name_end = content[name_start..]
+                .find(|c: char| !is_username_char(c))
+                .map(|len| name_start + len)
+                .unwrap_or(content.len());
+            // only at the start of a word, so email addresses don't count
+            let at_word_start = content[..at]
+                .chars()
+                .next_back()
+                .map(|c| !c.is_alphanumeric())
+                .unwrap_or(true);
+            let mentioned = (at_word_start && name_end > name_start)
+                .then(|| self.find_user_by_username(&content[name_start..name_end]))
+                .flatten();
+            if let Some(uid) = mentioned {
+                if plain_start < at {
+                    segments.push((content[plain_start..at].to_string(), None));
+                }
+                segments.push((content[at..name_end].to_string(), Some(uid)));
+                plain_start = name_end;
+            }
+            search_from = name_end;
+        }
+        if plain_start < content.len() {
+            segments.push((content[plain_start..].to_string(), None));
+        }
+        segments
+    }
+
+    /// Usernames (and the name we show, if different) of users where either starts with `prefix`
+    pub fn mention_suggestions(&self, prefix: &str) -> Vec<(String, Option<String>)> {
+        let prefix = prefix.to_lowercase();
+        let users: Vec<User> = self.users.with(|users| users.values().cloned().collect());
+        let mut suggestions: Vec<_> = users
+            .into_iter()
+            .map(|user| {
+                let shown = self
+                    .user_display_name(user.id)
+                    .filter(|name| *name != user.username);
+                (shown, user.username)
+            })
+            .filter(|(nickname, username)| {
+                username.to_lowercase().starts_with(&prefix)
+                    || nickname
+                        .as_ref()
+                        .map(|nickname| nickname.to_lowercase().starts_with(&prefix))
+                        .unwrap_or(false)
+            })
+            .map(|(nickname, username)| (username, nickname))
+            .collect();
+        suggestions.sort();
+        suggestions.truncate(MENTION_SUGGESTION_LIMIT);
+        suggestions
+    }
+
+    pub fn add_channels(&self, info: ChannelsInfo) {
+        let channels = info.channels;
+        for channel in channels {
+            self.add_channel(channel);
+        }
+    }
+
+    pub fn add_channel(&self, channel: Channel) {
+        let sid = channel.id.into();
+        // if mailbox doesn't exist, make one
+        let mailbox = self.mailbox_or_insert(sid, || channel.name.clone());
+        // it might have been made before we knew what it was called
+        mailbox.set_display_name(channel.name);
+        mailbox.set_archived(channel.archived);
+        mailbox.set_topic(channel.topic, channel.description);
+        mailbox.set_category(channel.category.filter(|category| !category.is_empty()));
+    }
+
+    pub fn change_topic(&self, changed: ChannelTopicChanged) {
+        let sid: SendableId = changed.id.into();
+        let Some(mailbox) = self.mailbox(sid) else {
+            return;
+        };
+        mailbox.set_topic(changed.topic.clone(), changed.description);
+
+        // and let everyone in the channel see it happen
+        let changed_by = self
+            .user_display_name(changed.changed_by)
+            .unwrap_or("someone".to_string());
+        let notice = if changed.topic.is_empty() {
+            format!("{changed_by} cleared the topic")
+        } else {
+            format!("{changed_by} set the topic to \"{}\"", changed.topic)
+        };
+        mailbox.add_notice(notice);
+    }
+
+    pub fn apply_read_markers(&self, read_markers: ReadMarkers) {
+        for marker in read_markers.markers {
+            if let Some(mailbox) = self.mailbox(marker.mailbox) {
+                mailbox.apply_read_marker(marker.message_id);
+            }
+        }
+    }
+
+    /// Marks the active mailbox read up to its newest message.
+    /// Returns what to tell the server, if anything changed.
+    pub fn mark_active_read(&self) -> Option<(SendableId, String)> {
+        let active_id = self.active_id.get();
+        let message_id = self.mailbox(active_id)?.mark_read()?;
+        Some((active_id, message_id))
+    }
+
+    /// Notices in the active mailbox after a message, or above them all for `None`
+    pub fn active_notices_after(&self, message_id: Option<&str>) -> Vec<String> {
+        self.mailbox(self.active_id.get())
+            .map(|mb| mb.get_notices_after(message_id))
+            .unwrap_or_default()
+    }
+
+    /// Id of the message the "new since you were last here" divider goes after
+    pub fn active_read_divider(&self) -> Option<String> {
+        self.mailbox(self.active_id.get())
+            .and_then(|mb| mb.get_divider())
+    }
+
+    /// Topic and description of the active mailbox, if it's a channel
+    pub fn active_topic(&self) -> Option<(String, String)> {
+        let active_id = self.active_id.get();
+        if !active_id.is_channel() {
+            return None;
+        }
+        self.mailbox(active_id).map(|mb| mb.get_topic())
+    }
+
+    pub fn rename_channel(&self, renamed: ChannelRenamed) {
+        if let Some(mailbox) = self.mailbox(renamed.id.into()) {
+            mailbox.set_display_name(renamed.name);
+        }
+    }
+
+    pub fn archive_channel(&self, archived: ChannelArchived) {
+        if let Some(mailbox) = self.mailbox(archived.id.into()) {
+            mailbox.set_archived(true);
+        }
+    }
+
+    pub fn remove_channel(&self, deleted: ChannelDeleted) {
+        let sid: SendableId = deleted.id.into();
+        self.mailboxes.update_value(|mailboxes| {
+            mailboxes.remove(&sid);
+        });
+        self.mailbox_keys.notify();
+        self.channel_members.update(|channel_members| {
+            channel_members.remove(&deleted.id);
+        });
+        self.membership.update(|membership| {
+            membership.remove(&deleted.id);
+        });
+        // its pins went with the mailbox, the rest would only lead to a channel that's gone
+        self.message_index
+            .update_value(|index| index.retain(|_, mailbox_id| *mailbox_id != sid));
+        self.search_index
+            .update_value(|index| index.remove_mailbox(sid));
+        if self
+            .slices
+            .bookmarks
+            .with_untracked(|bookmarks| bookmarks.iter().any(|(mailbox_id, _)| *mailbox_id == sid))
+        {
+            self.preferences.update(|preferences| {
+                preferences
+                    .bookmarks
+                    .retain(|(mailbox_id, _)| *mailbox_id != sid)
+            });
+            self.save_preferences();
+        }
+        // don't leave the user staring at a channel that's gone, notes to self if it was the last
+        if self.active_id.get_untracked() == sid {
+            let fallback: Option<SendableId> = self
+                .channel_list()
+                .first()
+                .map(|(cid, _)| (*cid).into())
+                .or_else(|| self.current_user_id.get_untracked().map(Into::into));
+            if let Some(fallback) = fallback {
+                self.set_active(fallback);
+            }
+        }
+    }
+
+    pub fn is_archived(&self, channel_id: ChannelId) -> bool {
+        self.mailbox(channel_id.into())
+            .map(|mb| mb.is_archived())
+            .unwrap_or(false)
+    }
+
+    pub fn set_channel_members(&self, members: ChannelMembers) {
+        let member_set: HashSet<UserId> = members.members.into_iter().collect();
+        // before login there's no telling whether we're one of them
+        if let Some(uid) = self.current_user_id.get_untracked() {
+            self.set_membership(members.id, member_set.contains(&uid));
+        }
+        self.channel_members.update(|channel_members| {
+            channel_members.insert(members.id, member_set);
+        });
+    }
+
+    pub fn add_channel_member(&self, joined: MemberJoined) {
+        if self.current_user_id.get_untracked() == Some(joined.user_id) {
+            self.mark_joined(joined.channel_id);
+        }
+        self.channel_members.update(|channel_members| {
+            channel_members
+                .entry(joined.channel_id)
+                .or_default()
+                .insert(joined.user_id);
+        });
+    }
+
+    pub fn remove_channel_member(&self, left: MemberLeft) {
+        if self.current_user_id.get_untracked() == Some(left.user_id) {
+            self.set_membership(left.channel_id, false);
+        }
+        self.channel_members.update(|channel_members| {
+            if let Some(members) = channel_members.get_mut(&left.channel_id) {
+                members.remove(&left.user_id);
+            }
+        });
+    }
+
+    /// Marks a channel we created as joined without waiting for the server
+    pub fn mark_joined(&self, channel_id: ChannelId) {
+        self.set_membership(channel_id, true);
+    }
+
+    fn set_membership(&self, channel_id: ChannelId, is_member: bool) {
+        if self
+            .membership
+            .with_untracked(|membership| membership.get(&channel_id) != Some(&is_member))
+        {
+            self.membership.update(|membership| {
+                membership.insert(channel_id, is_member);
+            });
+        }
+    }
+
+    /// `None` until the server has sent the channel's members
+    pub fn membership(&self, channel_id: ChannelId) -> Option<bool> {
+        self.membership
+            .with(|membership| membership.get(&channel_id).copied())
+    }
+
+    pub fn is_member(&self, channel_id: ChannelId) -> bool {
+        self.membership(channel_id).unwrap_or(false)
+    }
+
+    /// Whether we're still waiting to hear if we're in any channel
+    pub fn has_unknown_membership(&self) -> bool {
+        self.all_channels()
+            .iter()
+            .any(|(cid, _)| self.membership(*cid).is_none())
+    }
+
+    pub fn add_users(&self, info: UsersInfo) {
+        let new_users = info.users;
+        for user in new_users {
+            self.add_user(user);
+        }
+    }
+
+    /// Users we hear about are online, unless PresenceChanged already told us otherwise
+    pub fn add_user(&self, user: User) {
+        if self
+            .presence
+            .with_untracked(|presence| !presence.contains_key(&user.id))
+        {
+            self.presence.update(|presence| {
+                presence.insert(user.id, Presence::Online);
+            });
+        }
+        self.insert_user(user);
+    }
+
+    fn insert_user(&self, user: User) {
+        // keep the DM history if we've seen them before
+        let mailbox = self.mailbox_or_insert(user.id.into(), || user.username.clone());
+        mailbox.set_display_name(user.username.clone());
+        self.users.update(|users| {
+            users.insert(user.id, user);
+        });
+    }
+
+    /// Remembers a sender we don't know yet so it gets looked up
+    fn note_user(&self, user_id: UserId) {
+        // the server isn't a user
+        if user_id == UserId(0)
+            || self
+                .users
+                .with_untracked(|users| users.contains_key(&user_id))
+            || self
+                .looked_up
+                .with_value(|looked_up| looked_up.users.contains(&user_id))
+        {
+            return;
+        }
+        if self
+            .unresolved
+            .with_untracked(|unresolved| !unresolved.users.contains(&user_id))
+        {
+            self.unresolved.update(|unresolved| {
+                unresolved.users.insert(user_id);
+            });
+        }
+    }
+
+    /// Remembers a mailbox we don't know yet so it gets looked up
+    fn note_mailbox(&self, id: SendableId) {
+        match id {
+            SendableId::U(uid) => self.note_user(uid),
+            SendableId::C(cid) => {
+                let known = self
+                    .mailboxes
+                    .with_value(|mailboxes| mailboxes.contains_key(&id))
+                    || self
+                        .looked_up
+                        .with_value(|looked_up| looked_up.channels.contains(&cid));
+                if !known
+                    && self
+                        .unresolved
+                        .with_untracked(|unresolved| !unresolved.channels.contains(&cid))
+                {
+                    self.unresolved.update(|unresolved| {
+                        unresolved.channels.insert(cid);
+                    });
+                }
+            }
+            SendableId::G(gid) => {
+                let known = self
+                    .group_members
+                    .with_untracked(|group_members| group_members.contains_key(&gid))
+                    || self
+                        .looked_up
+                        .with_value(|looked_up| looked_up.groups.contains(&gid));
+                if !known
+                    && self
+                        .unresolved
+                        .with_untracked(|unresolved| !unresolved.groups.contains(&gid))
+                {
+                    self.unresolved.update(|unresolved| {
+                        unresolved.groups.insert(gid);
+                    });
+                }
+            }
+        }
+    }
+
+    pub fn has_unresolved(&self) -> bool {
+        self.unresolved.with(|unresolved| !unresolved.is_empty())
+    }
+
+    /// Everything waiting to be looked up, it won't be handed out again
+    pub fn take_unresolved(&self) -> Unresolved {
+        let unresolved = self.unresolved.get_untracked();
+        if unresolved.is_empty() {
+            return unresolved;
+        }
+        self.unresolved.set(Unresolved::default());
+        self.looked_up.update_value(|looked_up| {
+            looked_up.users.extend(unresolved.users.iter().copied());
+            looked_up
+                .channels
+                .extend(unresolved.channels.iter().copied());
+            looked_up.groups.extend(unresolved.groups.iter().copied());
+        });
+        unresolved
+    }
+
+    /// What the server told us about ids we asked after
+    pub fn resolve(&self, result: LookupResult) {
+        // only what this result answered, other lookups may still be in flight.
+        // Ids the server didn't know stay looked up, asking again would get the same answer
+        self.looked_up.update_value(|looked_up| {
+            for user in &result.users {
+                looked_up.users.remove(&user.id);
+            }
+            for channel in &result.channels {
+                looked_up.channels.remove(&channel.id);
+            }
+            for group in &result.groups {
+                looked_up.groups.remove(&group.id);
+            }
+        });
+        // presence isn't part of a lookup, PresenceChanged covers that
+        for user in result.users {
+            self.insert_user(user);
+        }
+        for channel in result.channels {
+            self.add_channel(channel);
+        }
+        for group in result.groups {
+            self.add_group(group);
+        }
+    }
+
+    /// Lookups still in flight are lost with the connection, so ask about those ids again
+    pub fn retry_lookups(&self) {
+        let looked_up = self
+            .looked_up
+            .try_update_value(std::mem::take)
+            .unwrap_or_default();
+        for user_id in looked_up.users {
+            self.note_user(user_id);
+        }
+        for channel_id in looked_up.channels {
+            self.note_mailbox(channel_id.into());
+        }
+        for group_id in looked_up.groups {
+            self.note_mailbox(group_id.into());
+        }
+    }
+
+    /// Users are never removed, they just go offline
+    pub fn set_presence(&self, user_id: UserId, presence: Presence) {
+        if self
+            .users
+            .with_untracked(|users| users.contains_key(&user_id))
+        {
+            self.presence.update(|all| {
+                all.insert(user_id, presence);
+            });
+        }
+    }
+
+    pub fn update_presence(&self, changed: PresenceChanged) {
+        self.set_presence(changed.id, changed.presence);
+    }
+
+    pub fn presence(&self, user_id: UserId) -> Presence {
+        self.presence
+            .with(|presence| presence.get(&user_id).copied().unwrap_or(Presence::Offline))
+    }
+
+    pub fn get_user(&self, user_id: UserId) -> Option<User> {
+        self.users.with(|users| users.get(&user_id).cloned())
+    }
+
+    /// Works out which mailbox something sent from `from` to `to` belongs in
+    fn route(&self, from: UserId, to: SendableId) -> SendableId {
+        // handle DMs, kinda tricky
+        match to {
+            SendableId::U(uid) => match self.current_user_id() {
+                Some(id) if id == uid => from.into(),
+                _ => uid.into(),
+            },
+            // channels and groups look the same to everyone in them
+            shared_to => shared_to,
+        }
+    }
+
+    pub fn add_message(&self, msg: ChatMessage) {
+        let mailbox_id = self.route(msg.from, msg.to);
+        let ignored = self.is_ignored(msg.from);
+        // DMs from ignored users are dropped, whether or not we've talked before.
+        // Anywhere else their messages are kept but collapsed, so replies still make sense
+        if ignored && mailbox_id.is_user() {
+            return;
+        }
+        self.note_user(msg.from);
+        self.note_mailbox(mailbox_id);
+
+        // remember where the message went so edits etc. can find it by id
+        self.message_index.update_value(|index| {
+            index.insert(msg.id.clone(), mailbox_id);
+        });
+        self.index_message(mailbox_id, &msg);
+
+        let mentions_me = !ignored && self.mentions_me(&msg.content);
+        let mailbox = self.mailbox_or_insert(mailbox_id, || "unknown".to_string());
+        let message_id = msg.id.clone();
+        mailbox.add_message(msg, !ignored);
+        self.adopt_orphans(&message_id);
+        if mentions_me {
+            mailbox.flag_mention();
+        }
+        // the open mailbox keeps what's been scrolled back through, until we're at the bottom again
+        if !mailbox.is_active() || self.at_bottom.get_value() {
+            let evicted = mailbox.evict(self.mailbox_capacity());
+            self.forget_messages(evicted);
+        }
+    }
+
+    /// Makes a message searchable, forgetting the mailbox's oldest if there are too many
+    fn index_message(&self, mailbox_id: SendableId, msg: &ChatMessage) {
+        let capacity = self.search_capacity();
+        self.search_index.update_value(|index| {
+            index.add(&msg.id, mailbox_id, msg.from, msg.ts, &msg.content);
+            index.trim_mailbox(mailbox_id, capacity);
+        });
+    }
+
+    fn search_capacity(&self) -> usize {
+        self.mailbox_capacity() * SEARCH_CAPACITY_FACTOR
+    }
+
+    /// The active mailbox was scrolled to or away from its newest message
+    pub fn set_at_bottom(&self, at_bottom: bool) {
+        self.at_bottom.set_value(at_bottom);
+        if !at_bottom {
+            return;
+        }
+        let capacity = self
+            .slices
+            .mailbox_capacity
+            .get_untracked()
+            .unwrap_or(DEFAULT_MAILBOX_CAPACITY);
+        if let Some(mailbox) = self.mailbox(self.active_id.get_untracked()) {
+            self.forget_messages(mailbox.evict(capacity));
+        }
+    }
+
+    pub fn add_groups(&self, info: GroupsInfo) {
+        for group in info.groups {
+            self.add_group(group);
+        }
+    }
+
+    pub fn add_group(&self, group: Group) {
+        // the name comes from the members, see group_name
+        self.mailbox_or_insert(group.id.into(), String::new);
+        self.group_members.update(|group_members| {
+            group_members.insert(group.id, group.members);
+        });
+    }
+
+    /// Everyone else in the group, e.g. "alice, bob, carol"
+    pub fn group_name(&self, group_id: GroupId) -> String {
+        let current_user_id = self.current_user_id();
+        let members = self
+            .group_members
+            .with(|group_members| group_members.get(&group_id).cloned().unwrap_or_default());
+        let mut names: Vec<String> = members
+            .into_iter()
+            .filter(|uid| Some(*uid) != current_user_id)
+            .map(|uid| {
+                self.user_display_name(uid)
+                    .unwrap_or("unknown user".to_string())
+            })
+            .collect();
+        if names.is_empty() {
+            return "unknown group".to_string();
+        }
+        names.sort();
+        names.join(", ")
+    }
+
+    /// A group with exactly these other members, so picking the same people again reopens it
+    pub fn find_group(&self, others: &HashSet<UserId>) -> Option<GroupId> {
+        let current_user_id = self.current_user_id();
+        self.group_members.with(|group_members| {
+            group_members
+                .iter()
+                .find(|(_, members)| {
+                    let members: HashSet<UserId> = members
+                        .iter()
+                        .copied()
+                        .filter(|uid| Some(*uid) != current_user_id)
+                        .collect();
+                    members == *others
+                })
+                .map(|(gid, _)| *gid)
+        })
+    }
+
+    /// Group conversations, favorites are listed separately
+    pub fn group_list(&self) -> Vec<(GroupId, String)> {
+        let group_ids: Vec<GroupId> = self
+            .group_members
+            .with(|group_members| group_members.keys().copied().collect());
+        let mut list: Vec<_> = group_ids
+            .into_iter()
+            .filter(|gid| !self.is_starred(*gid))
+            .map(|gid| (gid, self.group_name(gid)))
+            .collect();
+        list.sort_by(|a, b| a.1.cmp(&b.1));
+        self.apply_sort_order(SidebarSection::Groups, &mut list);
+        list
+    }
+
+    pub fn group_member_list(&self, group_id: GroupId) -> Vec<(UserId, String)> {
+        let members = self
+            .group_members
+            .with(|group_members| group_members.get(&group_id).cloned().unwrap_or_default());
+        let mut list: Vec<_> = members
+            .into_iter()
+            .map(|uid| {
+                let username = self
+                    .user_display_name(uid)
+                    .unwrap_or("unknown user".to_string());
+                (uid, username)
+            })
+            .collect();
+        self.sort_users(&mut list, None);
+        list
+    }
+
+    pub fn start_typing(&self, started: TypingStarted, now: f64) {
+        if self.is_ignored(started.from) {
+            return;
+        }
+        let mailbox_id = self.route(started.from, started.to);
+        if let Some(mailbox) = self.mailbox(mailbox_id) {
+            mailbox.set_typing(started.from, now + TYPING_TIMEOUT_MS);
         }
     }
 
-    fn get_display_name(&self) -> String {
-        self.display_name.borrow().clone()
+    pub fn stop_typing(&self, stopped: TypingStopped) {
+        let mailbox_id = self.route(stopped.from, stopped.to);
+        if let Some(mailbox) = self.mailbox(mailbox_id) {
+            mailbox.clear_typing(stopped.from);
+        }
     }
 
-    fn add_message(&self, msg: ChatMessage) {
-        let mut messages = self.messages.borrow_mut();
-        messages.push(msg);
-        if !*self.is_active.borrow() {
-            *self.has_unread.borrow_mut() = true;
-        } // else has_unread = false ?
+    /// Expires stale typing indicators everywhere
+    pub fn prune_typing(&self, now: f64) {
+        let mailboxes: Vec<Mailbox> = self
+            .mailboxes
+            .with_value(|mailboxes| mailboxes.values().copied().collect());
+        for mailbox in mailboxes {
+            mailbox.prune_typing(now);
+        }
     }
 
-    fn set_active(&self) {
-        *self.is_active.borrow_mut() = true;
-        *self.has_unread.borrow_mut() = false;
+    pub fn active_typing_usernames(&self) -> Vec<String> {
+        let current_user_id = self.current_user_id();
+        let typing = self
+            .mailbox(self.active_id.get())
+            .map(|mb| mb.get_typing())
+            .unwrap_or_default();
+        let mut usernames: Vec<_> = typing
+            .into_iter()
+            .filter(|uid| Some(*uid) != current_user_id)
+            .filter_map(|uid| self.user_display_name(uid))
+            .collect();
+        usernames.sort();
+        usernames
     }
 
-    fn set_inactive(&self) {
-        *self.is_active.borrow_mut() = false;
+    /// Evicted messages can't be found in their mailbox anymore.
+    /// They stay searchable for a while longer, see `stored_message`.
+    fn forget_messages(&self, message_ids: Vec<String>) {
+        if message_ids.is_empty() {
+            return;
+        }
+        self.message_index.update_value(|message_index| {
+            for id in &message_ids {
+                message_index.remove(id);
+            }
+        });
     }
 
-    fn is_active(&self) -> bool {
-        *self.is_active.borrow()
+    pub fn mailbox_capacity(&self) -> usize {
+        self.slices
+            .mailbox_capacity
+            .get()
+            .unwrap_or(DEFAULT_MAILBOX_CAPACITY)
     }
 
-    fn has_unread(&self) -> bool {
-        *self.has_unread.borrow()
+    pub fn show_plain_text(&self) -> bool {
+        self.slices.plain_text.get()
     }
 
-    fn get_messages(&self) -> Vec<ChatMessage> {
-        self.messages.borrow().clone()
+    pub fn set_plain_text(&self, plain_text: bool) {
+        self.preferences
+            .update(|preferences| preferences.plain_text = plain_text);
+        self.save_preferences();
     }
-}
 
-#[derive(Clone)]
-pub struct Mailroom {
-    active_id: Rc<RefCell<SendableId>>,
-    active_hook: Rc<RefCell<Option<Box<dyn FnMut() + 'static>>>>,
-    current_user_id: Rc<RefCell<Option<UserId>>>,
-    mailboxes: Rc<RefCell<HashMap<SendableId, Mailbox>>>,
-    users: Rc<RefCell<HashMap<UserId, User>>>,
-}
+    pub fn desktop_notifications(&self) -> bool {
+        self.slices.desktop_notifications.get()
+    }
 
-impl Mailroom {
-    pub fn new(active_id: impl Into<SendableId>) -> Self {
-        // todo: use Default to shorten this?
-        Self {
-            active_id: Rc::new(RefCell::new(active_id.into())),
-            active_hook: Rc::new(RefCell::new(None)),
-            current_user_id: Rc::new(RefCell::new(None)),
-            mailboxes: Rc::new(RefCell::new(HashMap::new())),
-            users: Rc::new(RefCell::new(HashMap::new())),
+    pub fn set_desktop_notifications(&self, enabled: bool) {
+        self.preferences
+            .update(|preferences| preferences.desktop_notifications = enabled);
+        self.save_preferences();
+    }
+
+    pub fn set_mailbox_capacity(&self, capacity: usize) {
+        self.preferences
+            .update(|preferences| preferences.mailbox_capacity = Some(capacity));
+        self.save_preferences();
+        let mailboxes: Vec<Mailbox> = self
+            .mailboxes
+            .with_value(|mailboxes| mailboxes.values().copied().collect());
+        let at_bottom = self.at_bottom.get_value();
+        let evicted: Vec<String> = mailboxes
+            .into_iter()
+            .filter(|mb| at_bottom || !mb.is_active.get_untracked())
+            .flat_map(|mb| mb.evict(capacity))
+            .collect();
+        self.forget_messages(evicted);
+        self.search_index
+            .update_value(|index| index.trim(capacity * SEARCH_CAPACITY_FACTOR));
+    }
+
+    /// Where to fetch older history from for the active mailbox, if it's worth asking
+    pub fn start_loading_history(&self) -> Option<(SendableId, String)> {
+        let active_id = self.active_id.get_untracked();
+        let oldest_id = self.mailbox(active_id)?.start_loading_history()?;
+        Some((active_id, oldest_id))
+    }
+
+    pub fn add_history(&self, history: History) {
+        let mailbox_id = history.mailbox;
+        for msg in &history.messages {
+            self.note_user(msg.from);
+            self.message_index.update_value(|index| {
+                index.insert(msg.id.clone(), mailbox_id);
+            });
+            self.index_message(mailbox_id, msg);
+        }
+        let message_ids: Vec<String> = history.messages.iter().map(|msg| msg.id.clone()).collect();
+        if let Some(mailbox) = self.mailbox(mailbox_id) {
+            mailbox.prepend_history(history.messages);
+        }
+        for message_id in message_ids {
+            self.adopt_orphans(&message_id);
         }
     }
 
-    pub fn set_current_user_id(&self, user_id: UserId) {
-        *self.current_user_id.borrow_mut() = Some(user_id);
+    fn find_message_mailbox(&self, message_id: &str) -> Option<Mailbox> {
+        let mailbox_id = self
+            .message_index
+            .with_value(|index| index.get(message_id).copied())?;
+        self.mailbox(mailbox_id)
     }
 
-    pub fn current_user_id(&self) -> Option<UserId> {
-        let current_user_id = self.current_user_id.borrow();
-        current_user_id.clone()
+    pub fn add_thread_reply(&self, reply: ThreadReply) {
+        // replies live in the same mailbox as their parent, just not in its timeline
+        let maybe_mailbox_id = self
+            .message_index
+            .with_value(|index| index.get(&reply.parent_id).copied());
+        let Some(mailbox_id) = maybe_mailbox_id else {
+            // the parent isn't loaded, e.g. it's further back in history, so wait for it
+            self.orphan_replies.update_value(|orphans| {
+                orphans
+                    .entry(reply.parent_id.clone())
+                    .or_default()
+                    .push(reply);
+            });
+            return;
+        };
+        let msg = &reply.message;
+        let ignored = self.is_ignored(msg.from);
+        // same as add_message, nothing from them in DMs
+        if ignored && mailbox_id.is_user() {
+            return;
+        }
+        self.note_user(msg.from);
+        self.message_index.update_value(|index| {
+            index.insert(msg.id.clone(), mailbox_id);
+        });
+        self.index_message(mailbox_id, msg);
+        if let Some(mailbox) = self.mailbox(mailbox_id) {
+            mailbox.add_thread_reply(reply.parent_id, reply.message, !ignored);
+        }
     }
 
-    pub fn add_channels(&self, info: ChannelsInfo) {
-        let mut mailboxes = self.mailboxes.borrow_mut();
-        let channels = info.channels;
-        for channel in channels {
-            let sid = channel.id.into();
-            // if mailbox doesn't exist, make one
-            mailboxes.entry(sid).or_insert(Mailbox::new(channel.name));
+    /// Files replies that were waiting on this message, now that it's here
+    fn adopt_orphans(&self, parent_id: &str) {
+        let orphans = self
+            .orphan_replies
+            .try_update_value(|orphans| orphans.remove(parent_id))
+            .flatten()
+            .unwrap_or_default();
+        for reply in orphans {
+            self.add_thread_reply(reply);
         }
     }
 
-    pub fn add_channel(&self, channel: Channel) {
-        let mut mailboxes = self.mailboxes.borrow_mut();
-        let sid = channel.id.into();
-        mailboxes.entry(sid).or_insert(Mailbox::new(channel.name));
+    pub fn get_message(&self, message_id: &str) -> Option<ChatMessage> {
+        self.find_message_mailbox(message_id)
+            .and_then(|mb| mb.get_message(message_id))
     }
 
-    pub fn add_users(&self, info: UsersInfo) {
-        let mut mailboxes = self.mailboxes.borrow_mut();
-        let mut users = self.users.borrow_mut();
-        let new_users = info.users;
-        for user in new_users {
-            let sid = user.id.into();
-            mailboxes
-                .entry(sid)
-                .or_insert(Mailbox::new(user.username.clone()));
-            users.entry(user.id).or_insert(user);
+    /// The message if it's loaded, else what the search index remembers of it after eviction
+    fn stored_message(&self, message_id: &str) -> Option<ChatMessage> {
+        self.get_message(message_id).or_else(|| {
+            self.search_index.with_value(|index| {
+                index.get(message_id).map(|indexed| ChatMessage {
+                    id: message_id.to_string(),
+                    from: indexed.from,
+                    // once filed, `to` only tells channel messages from DMs, and the mailbox does that too
+                    to: indexed.mailbox,
+                    content: indexed.content.clone(),
+                    ts: indexed.ts,
+                })
+            })
+        })
+    }
+
+    /// What a message says now, following edits and deletes
+    pub fn message_content(&self, message_id: &str) -> Option<String> {
+        self.find_message_mailbox(message_id)
+            .and_then(|mb| mb.get_content(message_id))
+    }
+
+    pub fn thread_replies(&self, parent_id: &str) -> Vec<Rc<ChatMessage>> {
+        self.find_message_mailbox(parent_id)
+            .map(|mb| mb.get_thread_replies(parent_id))
+            .unwrap_or_default()
+    }
+
+    pub fn reply_count(&self, parent_id: &str) -> usize {
+        self.find_message_mailbox(parent_id)
+            .map(|mb| mb.reply_count(parent_id))
+            .unwrap_or(0)
+    }
+
+    pub fn open_thread(&self, parent_id: String) {
+        // one side panel at a time
+        self.set_side_panel(None);
+        self.active_thread.set(Some(parent_id));
+    }
+
+    pub fn close_thread(&self) {
+        set_if_changed(self.active_thread, None);
+    }
+
+    pub fn active_thread(&self) -> Option<String> {
+        self.active_thread.get()
+    }
+
+    pub fn edit_message(&self, edit: ChatMessageEdited) {
+        // the index has trimmed messages too, it gets the edit either way
+        self.search_index
+            .update_value(|index| index.edit(&edit.id, &edit.content));
+        if let Some(mailbox) = self.find_message_mailbox(&edit.id) {
+            mailbox.edit_message(&edit.id, edit.content);
         }
     }
 
-    pub fn add_user(&self, user: User) {
-        let mut mailboxes = self.mailboxes.borrow_mut();
-        let mut users = self.users.borrow_mut();
-        let sid = user.id.into();
-        mailboxes.insert(sid, Mailbox::new(user.username.clone()));
-        users.insert(user.id, user);
+    pub fn delete_message(&self, deletion: ChatMessageDeleted) {
+        if let Some(mailbox) = self.find_message_mailbox(&deletion.id) {
+            mailbox.delete_message(&deletion.id);
+        }
+        self.search_index
+            .update_value(|index| index.remove(&deletion.id));
+    }
+
+    /// Searches every mailbox, newest first.
+    /// Supports `from:user`, `in:#channel`, `in:@user`, `after:YYYY-MM-DD` and `before:YYYY-MM-DD`.
+    pub fn search(&self, query: &str) -> Vec<(SendableId, ChatMessage)> {
+        let query = Query::parse(query);
+        if query.is_empty() {
+            return vec![];
+        }
+
+        // nicknames work anywhere a username does
+        let nickname_matches = |uid: UserId, name: &str| {
+            self.nickname(uid)
+                .map(|nickname| nickname.to_lowercase().starts_with(name))
+                .unwrap_or(false)
+        };
+
+        let from: Option<Vec<UserId>> = query.from.as_ref().map(|name| {
+            self.users.with(|users| {
+                users
+                    .iter()
+                    .filter(|(uid, user)| {
+                        user.username.to_lowercase().starts_with(name.as_str())
+                            || nickname_matches(**uid, name)
+                    })
+                    .map(|(uid, _)| *uid)
+                    .collect()
+            })
+        });
+
+        let in_mailbox: Option<Vec<SendableId>> = query.in_mailbox.as_ref().map(|name| {
+            self.mailbox_keys.track();
+            self.mailboxes.with_value(|mailboxes| {
+                mailboxes
+                    .iter()
+                    .filter(|(sid, mb)| {
+                        let display_name = mb.get_display_name().to_lowercase();
+                        if let Some(channel_name) = name.strip_prefix('#') {
+                            sid.is_channel() && display_name == channel_name
+                        } else if let Some(username) = name.strip_prefix('@') {
+                            let nickname = match sid {
+                                SendableId::U(uid) => self.nickname(*uid),
+                                _ => None,
+                            };
+                            sid.is_user()
+                                && (display_name == username
+                                    || nickname.map(|nickname| nickname.to_lowercase())
+                                        == Some(username.to_string()))
+                        } else {
+                            display_name == *name
+                        }
+                    })
+                    .map(|(sid, _)| *sid)
+                    .collect()
+            })
+        });
+
+        let ignored = self.slices.ignored.get();
+        let results = self.search_index.with_value(|index| {
+            index.search(&query, from.as_deref(), in_mailbox.as_deref(), &ignored)
+        });
+        results
+            .into_iter()
+            .filter_map(|(id, indexed)| self.stored_message(&id).map(|msg| (indexed.mailbox, msg)))
+            .take(SEARCH_RESULT_LIMIT)
+            .collect()
     }
 
-    pub fn remove_user(&self, user_id: UserId) {
-        let mut mailboxes = self.mailboxes.borrow_mut();
-        let mut users = self.users.borrow_mut();
-        mailboxes.remove(&user_id.into());
-        users.remove(&user_id);
+    /// Opens the mailbox (and thread) a message is in and highlights it
+    pub fn jump_to(&self, mailbox_id: SendableId, message_id: String) {
+        self.set_active(mailbox_id);
+        let maybe_parent_id = self
+            .mailbox(mailbox_id)
+            .and_then(|mb| mb.thread_parent(&message_id));
+        if let Some(parent_id) = maybe_parent_id {
+            self.open_thread(parent_id);
+        }
+        self.highlighted.set(Some(message_id));
     }
 
-    pub fn get_user(&self, user_id: UserId) -> Option<User> {
-        self.users.borrow().get(&user_id).cloned()
+    /// Whether a transcript from `after` on would miss history that isn't loaded
+    pub fn is_transcript_partial(&self, mailbox_id: SendableId, after: Option<f64>) -> bool {
+        self.mailbox(mailbox_id)
+            .is_some_and(|mb| mb.is_missing_before(after))
     }
 
-    pub fn add_message(&self, msg: ChatMessage) {
-        let mut mailboxes = self.mailboxes.borrow_mut();
-        // handle DMs, kinda tricky
-        let mailbox_id = match msg.to {
-            SendableId::U(uid) => {
-                let current_user_id = self.current_user_id.borrow();
-                match current_user_id.as_ref() {
-                    Some(id) if *id == uid => msg.from.into(),
-                    _ => uid.into(),
-                }
+    /// Everything loaded in a mailbox between `after` and `before`, ready to export,
+    /// see `is_transcript_partial` for whether that's everything said.
+    /// Replies come along with the message they're under, whenever they were sent.
+    pub fn transcript(
+        &self,
+        mailbox_id: SendableId,
+        after: Option<f64>,
+        before: Option<f64>,
+    ) -> Option<Transcript> {
+        let title = self.display_name(mailbox_id)?;
+        let mailbox = self.mailbox(mailbox_id)?;
+        let export = |chat: &ChatMessage, replies: Vec<ExportedMessage>| {
+            let username = match self.user_display_name(chat.from) {
+                Some(username) => username,
+                None if chat.from == UserId(0) => "server".to_string(),
+                None => "unknown user".to_string(),
+            };
+            ExportedMessage {
+                edited: mailbox.is_edited(&chat.id),
+                timestamp: format_timestamp(chat.ts),
+                id: chat.id.clone(),
+                username,
+                flair: self.get_user(chat.from).and_then(|user| user.flair),
+                ts: chat.ts,
+                content: chat.content.clone(),
+                replies,
             }
-            channel_to => channel_to,
         };
+        let messages = mailbox
+            .get_messages()
+            .into_iter()
+            .filter(|chat| !mailbox.is_deleted(&chat.id))
+            .filter(|chat| after.map(|after| chat.ts >= after).unwrap_or(true))
+            .filter(|chat| before.map(|before| chat.ts < before).unwrap_or(true))
+            .map(|chat| {
+                let replies = mailbox
+                    .get_thread_replies(&chat.id)
+                    .into_iter()
+                    .filter(|reply| !mailbox.is_deleted(&reply.id))
+                    .map(|reply| export(&reply, vec![]))
+                    .collect();
+                export(&chat, replies)
+            })
+            .collect();
+        Some(Transcript { title, messages })
+    }
+
+    pub fn set_pins(&self, pins: ChannelPins) {
+        if let Some(mailbox) = self.mailbox(pins.id.into()) {
+            mailbox.set_pins(pins.message_ids);
+        }
+    }
 
-        let entry = mailboxes
-            .entry(mailbox_id)
-            .or_insert(Mailbox::new("unknown".to_string()));
-        entry.add_message(msg);
+    pub fn pin_message(&self, pinned: MessagePinned) {
+        if let Some(mailbox) = self.mailbox(pinned.channel.into()) {
+            mailbox.pin(pinned.message_id);
+        }
     }
 
-    pub fn channel_list(&self) -> Vec<(ChannelId, String)> {
-        let mailboxes = self.mailboxes.borrow();
-        let mut list: Vec<_> = mailboxes
+    pub fn unpin_message(&self, unpinned: MessageUnpinned) {
+        if let Some(mailbox) = self.mailbox(unpinned.channel.into()) {
+            mailbox.unpin(&unpinned.message_id);
+        }
+    }
+
+    pub fn is_pinned(&self, message_id: &str) -> bool {
+        self.find_message_mailbox(message_id)
+            .map(|mb| mb.is_pinned(message_id))
+            .unwrap_or(false)
+    }
+
+    /// Pinned messages we've seen, most recently pinned first
+    pub fn pins(&self, id: impl Into<SendableId>) -> Vec<ChatMessage> {
+        let Some(mailbox) = self.mailbox(id.into()) else {
+            return vec![];
+        };
+        mailbox
+            .get_pins()
+            .iter()
+            .rev()
+            .filter_map(|message_id| self.stored_message(message_id))
+            .collect()
+    }
+
+    pub fn toggle_bookmark(&self, message_id: &str) {
+        let bookmarked = self.preferences.with_untracked(|preferences| {
+            preferences.bookmarks.iter().any(|(_, id)| id == message_id)
+        });
+        if bookmarked {
+            self.remove_bookmark(message_id);
+            return;
+        }
+        // saving needs to know where it's from, unsaving doesn't
+        let Some(mailbox_id) = self
+            .message_index
+            .with_value(|index| index.get(message_id).copied())
+        else {
+            return;
+        };
+        self.preferences.update(|preferences| {
+            preferences
+                .bookmarks
+                .push((mailbox_id, message_id.to_string()));
+        });
+        self.save_preferences();
+    }
+
+    /// Works whether or not we still have the message
+    pub fn remove_bookmark(&self, message_id: &str) {
+        self.preferences.update(|preferences| {
+            preferences.bookmarks.retain(|(_, id)| id != message_id);
+        });
+        self.save_preferences();
+    }
+
+    pub fn is_bookmarked(&self, message_id: &str) -> bool {
+        self.slices
+            .bookmarks
+            .with(|bookmarks| bookmarks.iter().any(|(_, id)| id == message_id))
+    }
+
+    /// Bookmarks, most recently saved first, with the message if we still have it
+    pub fn saved_items(&self) -> Vec<(SendableId, String, Option<ChatMessage>)> {
+        let bookmarks = self.slices.bookmarks.get();
+        bookmarks
+            .into_iter()
+            .rev()
+            .map(|(mailbox_id, message_id)| {
+                let chat = self.stored_message(&message_id);
+                (mailbox_id, message_id, chat)
+            })
+            .collect()
+    }
+
+    pub fn set_side_panel(&self, side_panel: Option<SidePanel>) {
+        if side_panel.is_some() {
+            self.close_thread();
+        }
+        self.side_panel.set(side_panel);
+    }
+
+    /// Opens the panel, or closes it if it's already open
+    pub fn toggle_side_panel(&self, side_panel: SidePanel) {
+        let open = self.side_panel.get_untracked() != Some(side_panel);
+        self.set_side_panel(open.then_some(side_panel));
+    }
+
+    pub fn side_panel(&self) -> Option<SidePanel> {
+        self.side_panel.get()
+    }
+
+    pub fn is_highlighted(&self, message_id: &str) -> bool {
+        self.highlighted
+            .with(|highlighted| highlighted.as_deref() == Some(message_id))
+    }
+
+    pub fn add_reaction(&self, reaction: ReactionAdded) {
+        if let Some(mailbox) = self.find_message_mailbox(&reaction.message_id) {
+            mailbox.add_reaction(&reaction.message_id, reaction.emoji, reaction.user_id);
+        }
+    }
+
+    pub fn remove_reaction(&self, reaction: ReactionRemoved) {
+        if let Some(mailbox) = self.find_message_mailbox(&reaction.message_id) {
+            mailbox.remove_reaction(&reaction.message_id, &reaction.emoji, reaction.user_id);
+        }
+    }
+
+    pub fn reactions(&self, message_id: &str) -> Reactions {
+        self.find_message_mailbox(message_id)
+            .map(|mb| mb.get_reactions(message_id))
+            .unwrap_or_default()
+    }
+
+    /// Whether we've already reacted to the message with `emoji`
+    pub fn has_reacted(&self, message_id: &str, emoji: &str) -> bool {
+        let Some(user_id) = self.current_user_id() else {
+            return false;
+        };
+        self.reactions(message_id)
             .iter()
-            .filter(|(sid, _)| sid.is_channel())
-            .map(|(sid, mb)| match sid {
-                SendableId::C(cid) => (*cid, mb.display_name.borrow().clone()),
-                _ => unreachable!("has to be a channel"),
+            .any(|(reaction, user_ids)| reaction == emoji && user_ids.contains(&user_id))
+    }
+
+    pub fn is_edited(&self, message_id: &str) -> bool {
+        self.find_message_mailbox(message_id)
+            .map(|mb| mb.is_edited(message_id))
+            .unwrap_or(false)
+    }
+
+    pub fn is_deleted(&self, message_id: &str) -> bool {
+        self.find_message_mailbox(message_id)
+            .map(|mb| mb.is_deleted(message_id))
+            .unwrap_or(false)
+    }
+
+    fn all_channels(&self) -> Vec<(ChannelId, String)> {
+        self.mailbox_keys.track();
+        let mut list: Vec<_> = self.mailboxes.with_value(|mailboxes| {
+            mailboxes
+                .iter()
+                .filter(|(sid, _)| sid.is_channel())
+                .map(|(sid, mb)| match sid {
+                    SendableId::C(cid) => (*cid, mb.get_display_name()),
+                    _ => unreachable!("has to be a channel"),
+                })
+                .collect()
+        });
+        list.sort_by(|a, b| a.1.cmp(&b.1));
+        list
+    }
+
+    /// Channels the current user has joined, favorites are listed separately
+    pub fn channel_list(&self) -> Vec<(ChannelId, String)> {
+        let mut list = self.all_channels();
+        list.retain(|(cid, _)| {
+            self.is_member(*cid) && !self.is_archived(*cid) && !self.is_starred(*cid)
+        });
+        self.apply_sort_order(SidebarSection::Channels, &mut list);
+        list
+    }
+
+    /// Starred channels and users, with the display name to show for them
+    pub fn favorite_list(&self) -> Vec<(SendableId, String)> {
+        let starred = self.slices.starred.get();
+        let mut list: Vec<_> = starred
+            .into_iter()
+            .filter_map(|id| match id {
+                SendableId::C(cid) if self.is_archived(cid) => None,
+                SendableId::C(_) => Some((id, self.mailbox(id)?.get_display_name())),
+                SendableId::U(uid) if self.is_ignored(uid) => None,
+                SendableId::U(uid) => Some((id, self.user_display_name(uid)?)),
+                SendableId::G(gid) => Some((id, self.group_name(gid))),
+            })
+            .collect();
+        list.sort_by(|a, b| a.1.cmp(&b.1));
+        self.apply_sort_order(SidebarSection::Favorites, &mut list);
+        list
+    }
+
+    pub fn is_starred(&self, id: impl Into<SendableId>) -> bool {
+        let mailbox_id = id.into();
+        self.slices
+            .starred
+            .with(|starred| starred.contains(&mailbox_id))
+    }
+
+    pub fn toggle_starred(&self, id: impl Into<SendableId>) {
+        let mailbox_id = id.into();
+        self.preferences.update(|preferences| {
+            let starred = &mut preferences.starred;
+            if !starred.remove(&mailbox_id) {
+                starred.insert(mailbox_id);
+            }
+        });
+        self.save_preferences();
+    }
+
+    /// The category a channel is filed under, ours if we picked one, else the server's
+    pub fn channel_category(&self, channel_id: ChannelId) -> Option<String> {
+        self.slices
+            .categories
+            .with(|categories| categories.get(&channel_id).cloned())
+            .or_else(|| {
+                self.mailbox(channel_id.into())
+                    .and_then(|mb| mb.get_category())
+            })
+    }
+
+    /// Files a channel under a category, blank goes back to the server's
+    pub fn set_channel_category(&self, channel_id: ChannelId, category: &str) {
+        let category = category.trim().to_string();
+        self.preferences.update(|preferences| {
+            if category.is_empty() {
+                preferences.categories.remove(&channel_id);
+            } else {
+                preferences.categories.insert(channel_id, category);
+            }
+        });
+        self.save_preferences();
+    }
+
+    /// Categories with joined channels in them, uncategorized (`None`) first
+    pub fn category_list(&self) -> Vec<Option<String>> {
+        let mut categories: Vec<_> = self
+            .channel_list()
+            .into_iter()
+            .map(|(cid, _)| self.channel_category(cid))
+            .collect();
+        categories.sort();
+        categories.dedup();
+        categories
+    }
+
+    pub fn category_channels(&self, category: &Option<String>) -> Vec<(ChannelId, String)> {
+        let mut list = self.channel_list();
+        list.retain(|(cid, _)| self.channel_category(*cid) == *category);
+        list
+    }
+
+    /// How many channels in a category are unread, and whether any of them mention us
+    pub fn category_unread(&self, category: &Option<String>) -> (usize, bool) {
+        self.category_channels(category)
+            .into_iter()
+            .filter(|(cid, _)| self.has_unread(*cid))
+            .fold((0, false), |(count, mentioned), (cid, _)| {
+                (count + 1, mentioned || self.has_mention(cid))
             })
+    }
+
+    pub fn is_collapsed(&self, category: &str) -> bool {
+        self.slices
+            .collapsed
+            .with(|collapsed| collapsed.contains(category))
+    }
+
+    pub fn toggle_collapsed(&self, category: &str) {
+        self.preferences.update(|preferences| {
+            let collapsed = &mut preferences.collapsed;
+            if !collapsed.remove(category) {
+                collapsed.insert(category.to_string());
+            }
+        });
+        self.save_preferences();
+    }
+
+    pub fn sort_order(&self) -> SortOrder {
+        self.slices.sort_order.get()
+    }
+
+    pub fn set_sort_order(&self, sort_order: SortOrder) {
+        self.preferences
+            .update(|preferences| preferences.sort_order = sort_order);
+        self.save_preferences();
+    }
+
+    /// Drops `dragged` in front of `target` in a section. `visible` is the section as currently
+    /// shown, so anything that was never dragged keeps its place.
+    pub fn move_mailbox(
+        &self,
+        section: SidebarSection,
+        dragged: SendableId,
+        target: SendableId,
+        visible: &[SendableId],
+    ) {
+        self.preferences.update(|preferences| {
+            let order = preferences.manual_orders.entry(section).or_default();
+            for id in visible {
+                if !order.contains(id) {
+                    order.push(*id);
+                }
+            }
+            order.retain(|id| *id != dragged);
+            let position = order
+                .iter()
+                .position(|id| *id == target)
+                .unwrap_or(order.len());
+            order.insert(position, dragged);
+        });
+        self.save_preferences();
+    }
+
+    /// Re-sorts an already alphabetical list by the chosen sort order, ties keep their place
+    fn apply_sort_order<T: Copy + Into<SendableId>>(
+        &self,
+        section: SidebarSection,
+        list: &mut [(T, String)],
+    ) {
+        if self.sort_order() != SortOrder::Alphabetical {
+            list.sort_by(|a, b| self.compare_by_sort_order(section, a.0.into(), b.0.into()));
+        }
+    }
+
+    /// How two entries of a section compare under the chosen sort order, alphabetical is a tie
+    fn compare_by_sort_order(
+        &self,
+        section: SidebarSection,
+        a: SendableId,
+        b: SendableId,
+    ) -> std::cmp::Ordering {
+        match self.sort_order() {
+            SortOrder::Alphabetical => std::cmp::Ordering::Equal,
+            SortOrder::Recent => {
+                let last_activity =
+                    |id: SendableId| self.mailbox(id).map(|mb| mb.last_activity()).unwrap_or(0.0);
+                last_activity(b).total_cmp(&last_activity(a))
+            }
+            SortOrder::UnreadFirst => self.has_unread(b).cmp(&self.has_unread(a)),
+            SortOrder::Manual => self.slices.manual_orders.with(|manual_orders| {
+                let position = |id: SendableId| {
+                    manual_orders
+                        .get(&section)
+                        .and_then(|order| order.iter().position(|ordered| *ordered == id))
+                        .unwrap_or(usize::MAX)
+                };
+                position(a).cmp(&position(b))
+            }),
+        }
+    }
+
+    /// Channels the current user could join
+    pub fn browse_channel_list(&self) -> Vec<(ChannelId, String)> {
+        let mut list = self.all_channels();
+        list.retain(|(cid, _)| self.membership(*cid) == Some(false) && !self.is_archived(*cid));
+        list
+    }
+
+    /// Archived channels are read-only, but everyone can still read them
+    pub fn archived_channel_list(&self) -> Vec<(ChannelId, String)> {
+        let mut list = self.all_channels();
+        list.retain(|(cid, _)| self.is_archived(*cid));
+        list
+    }
+
+    /// Everyone but the current user, by name
+    pub fn other_users(&self) -> Vec<(UserId, String)> {
+        let current_user_id = self.current_user_id();
+        let user_ids: Vec<UserId> = self.users.with(|users| users.keys().copied().collect());
+        let mut list: Vec<_> = user_ids
+            .into_iter()
+            .filter(|uid| Some(*uid) != current_user_id)
+            .filter_map(|uid| Some((uid, self.user_display_name(uid)?)))
             .collect();
         list.sort_by(|a, b| a.1.cmp(&b.1));
         list
     }
 
     pub fn user_list(&self) -> Vec<(UserId, String)> {
-        let users = self.users.borrow();
-        let current_user_id = *self.current_user_id.borrow();
-        let mut list: Vec<_> = users
-            .iter()
-            .map(|(uid, user)| (*uid, user.username.clone()))
+        let user_ids: Vec<UserId> = self.users.with(|users| users.keys().copied().collect());
+        let mut list: Vec<_> = user_ids
+            .into_iter()
+            .filter(|uid| !self.is_starred(*uid) && !self.is_ignored(*uid))
+            .filter_map(|uid| Some((uid, self.user_display_name(uid)?)))
             .collect();
+        self.sort_users(&mut list, Some(SidebarSection::Users));
+        list
+    }
+
+    pub fn member_list(&self, channel_id: ChannelId) -> Vec<(UserId, String)> {
+        let member_ids: Vec<UserId> = self.channel_members.with(|channel_members| {
+            channel_members
+                .get(&channel_id)
+                .into_iter()
+                .flatten()
+                .copied()
+                .collect()
+        });
+        let mut list: Vec<_> = member_ids
+            .into_iter()
+            .map(|uid| {
+                let username = self
+                    .user_display_name(uid)
+                    .unwrap_or("unknown user".to_string());
+                (uid, username)
+            })
+            .collect();
+        self.sort_users(&mut list, None);
+        list
+    }
+
+    /// Current user on top, then by presence. In a sidebar `section` the sort order breaks ties
+    /// before the name does.
+    fn sort_users(&self, list: &mut [(UserId, String)], section: Option<SidebarSection>) {
+        let current_user_id = self.current_user_id();
+
+        // online first, then away, then offline
+        let presence_rank = |uid: UserId| match self.presence(uid) {
+            Presence::Online => 0,
+            Presence::Away => 1,
+            Presence::Offline => 2,
+        };
 
         list.sort_by(|a, b| {
-            // sorting users is slightly trickier, want your current user at the top, all else alphabetical
+            // sorting users is slightly trickier, want your current user at the top, all else by presence then alphabetical
             if let Some(current_uid) = current_user_id {
                 if a.0 == current_uid {
-                    std::cmp::Ordering::Less
+                    return std::cmp::Ordering::Less;
                 } else if b.0 == current_uid {
-                    std::cmp::Ordering::Greater
-                } else {
-                    a.1.cmp(&b.1)
+                    return std::cmp::Ordering::Greater;
                 }
-            } else {
-                // we don't know which user we are, so just normal sort
-                a.1.cmp(&b.1)
             }
+            presence_rank(a.0)
+                .cmp(&presence_rank(b.0))
+                .then_with(|| {
+                    section
+                        .map(|section| self.compare_by_sort_order(section, a.0.into(), b.0.into()))
+                        .unwrap_or(std::cmp::Ordering::Equal)
+                })
+                .then_with(|| a.1.cmp(&b.1))
         });
-        list
+    }
+
+    pub fn set_browsing(&self, browsing: bool) {
+        if browsing {
+            self.close_thread();
+        }
+        set_if_changed(self.browsing, browsing);
+    }
+
+    pub fn is_browsing(&self) -> bool {
+        self.browsing.get()
     }
 
     pub fn active_selection(&self) -> SendableId {
-        *self.active_id.borrow()
+        self.active_id.get()
+    }
+
+    /// Whether the current user can post in the active mailbox
+    pub fn can_send_to_active(&self) -> bool {
+        match self.active_selection() {
+            SendableId::C(cid) => self.is_member(cid) && !self.is_archived(cid),
+            _ => true,
+        }
     }
 
-    pub fn active_messages(&self) -> Vec<ChatMessage> {
-        let active_id = self.active_id.borrow();
-        let mailboxes = self.mailboxes.borrow();
-        mailboxes
-            .get(&*active_id)
+    pub fn active_messages(&self) -> Vec<Rc<ChatMessage>> {
+        self.mailbox(self.active_id.get())
             .map(|mb| mb.get_messages())
             .unwrap_or(vec![])
     }
 
     pub fn active_display_name(&self) -> Option<String> {
-        let active_id = self.active_id.borrow();
-        let mailboxes = self.mailboxes.borrow();
-        if let Some(mailbox) = mailboxes.get(&*active_id) {
-            if active_id.is_user() {
-                return Some(format!("@{}", mailbox.get_display_name()));
-            } else {
-                return Some(format!("#{}", mailbox.get_display_name()));
+        self.display_name(self.active_selection())
+    }
+
+    pub fn display_name(&self, id: SendableId) -> Option<String> {
+        let mailbox = self.mailbox(id)?;
+        match id {
+            SendableId::U(uid) => {
+                let name = self
+                    .user_display_name(uid)
+                    .unwrap_or(mailbox.get_display_name());
+                Some(format!("@{name}"))
             }
+            SendableId::G(gid) => Some(self.group_name(gid)),
+            _ => Some(format!("#{}", mailbox.get_display_name())),
         }
-        None
     }
 
     pub fn set_active(&self, id: impl Into<SendableId>) {
         let mailbox_id = id.into();
-        let mut active_id = self.active_id.borrow_mut();
-        let mailboxes = self.mailboxes.borrow();
-        // tell the old mailbox no longer active
-        if let Some(mailbox) = mailboxes.get(&*active_id) {
-            mailbox.set_inactive()
+        // tell the old mailbox no longer active, and let go of any history scrolled back through
+        if let Some(mailbox) = self.mailbox(self.active_id.get_untracked()) {
+            mailbox.set_inactive();
+            self.forget_messages(mailbox.evict(self.mailbox_capacity()));
         }
         // update mailroom
-        *active_id = mailbox_id;
+        self.active_id.set(mailbox_id);
+        // threads belong to the old mailbox
+        self.close_thread();
+        set_if_changed(self.highlighted, None);
+        self.set_browsing(false);
         // update the new mailbox
-        if let Some(mailbox) = mailboxes.get(&*active_id) {
+        if let Some(mailbox) = self.mailbox(mailbox_id) {
             mailbox.set_active()
         }
         // also call the hook if it's set
-        let mut hook = self.active_hook.borrow_mut();
-        if let Some(mut box_f) = hook.take() {
+        let hook = self.active_hook.try_update_value(|hook| hook.take());
+        if let Some(mut box_f) = hook.flatten() {
             box_f();
             // then put it back
-            *hook = Some(box_f);
+            self.active_hook.set_value(Some(box_f));
         }
     }
 
     pub fn set_active_hook(&self, f: impl FnMut() + 'static) {
-        *self.active_hook.borrow_mut() = Some(Box::new(f));
+        self.active_hook.set_value(Some(Box::new(f)));
     }
 
     pub fn is_active(&self, id: impl Into<SendableId>) -> bool {
-        let mailbox_id = id.into();
-        let mailboxes = self.mailboxes.borrow();
-        mailboxes
-            .get(&mailbox_id)
+        self.mailbox(id.into())
             .map(|mb| mb.is_active())
             .unwrap_or(false)
     }
 
+    /// Whether to badge the mailbox as unread, going by its notification level
     pub fn has_unread(&self, id: impl Into<SendableId>) -> bool {
         let mailbox_id = id.into();
-        let mailboxes = self.mailboxes.borrow();
-        mailboxes
-            .get(&mailbox_id)
-            .map(|mb| mb.has_unread())
+        let level = self.notify_level(mailbox_id);
+        self.mailbox(mailbox_id)
+            .map(|mb| match level {
+                NotifyLevel::All => mb.has_unread(),
+                NotifyLevel::Mentions => mb.has_mention(),
+                NotifyLevel::Muted => false,
+            })
             .unwrap_or(false)
     }
+
+    pub fn has_mention(&self, id: impl Into<SendableId>) -> bool {
+        let mailbox_id = id.into();
+        !self.is_muted(mailbox_id)
+            && self
+                .mailbox(mailbox_id)
+                .map(|mb| mb.has_mention())
+                .unwrap_or(false)
+    }
+
+    pub fn is_muted(&self, id: impl Into<SendableId>) -> bool {
+        self.notify_level(id) == NotifyLevel::Muted
+    }
 }
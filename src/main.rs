@@ -1,5 +1,11 @@
 mod components;
+mod export;
+mod highlight;
 mod mailroom;
+mod markdown;
+mod search;
+mod storage;
+mod util;
 mod ws;
 
 use crate::components::App;
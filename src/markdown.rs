@@ -0,0 +1,734 @@
+//! The bit of CommonMark chat needs: bold, italics, code, fenced code, lists, quotes and links.
+//!
+//! Messages are parsed into a tree and rendered as elements and text nodes, so HTML someone
+//! types in is only ever shown as text, never parsed by the browser.
+
+use std::collections::HashMap;
+
+/// A block of a message, paragraphs and the like
+#[derive(Clone, Debug, PartialEq)]
+pub enum Block {
+    Paragraph(Vec<Inline>),
+    /// Fenced code, with the language from the info string if there was one
+    Code {
+        lang: Option<String>,
+        code: String,
+    },
+    Quote(Vec<Block>),
+    List {
+        ordered: bool,
+        items: Vec<Vec<Block>>,
+    },
+}
+
+/// Styled runs of text inside a block
+#[derive(Clone, Debug, PartialEq)]
+pub enum Inline {
+    Text(String),
+    Strong(Vec<Inline>),
+    Emphasis(Vec<Inline>),
+    Code(String),
+    /// Only http(s) and mailto links survive parsing, anything else is left as its text
+    Link {
+        url: String,
+        children: Vec<Inline>,
+    },
+    /// Chat keeps the line breaks people type
+    LineBreak,
+}
+
+/// How deep quotes, lists, emphasis and links can nest, markers past this are left as text
+const MAX_NESTING: usize = 16;
+
+pub fn parse(text: &str) -> Vec<Block> {
+    let lines: Vec<&str> = text.lines().collect();
+    parse_blocks(&lines, 0)
+}
+
+fn parse_blocks(lines: &[&str], depth: usize) -> Vec<Block> {
+    let can_nest = depth < MAX_NESTING;
+    let mut blocks = vec![];
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        if line.trim().is_empty() {
+            i += 1;
+            continue;
+        }
+
+        if let Some((fence, info)) = opening_fence(line) {
+            i += 1;
+            let start = i;
+            while i < lines.len() && !closes_fence(lines[i], fence) {
+                i += 1;
+            }
+            let code = lines[start..i].join("\n");
+            // skip the closing fence, an unclosed block runs to the end of the message
+            i += 1;
+            let lang = info
+                .split_whitespace()
+                .next()
+                .map(|lang| lang.to_lowercase());
+            blocks.push(Block::Code { lang, code });
+            continue;
+        }
+
+        if let Some(first) = strip_quote(line).filter(|_| can_nest) {
+            let mut quoted = vec![first];
+            i += 1;
+            while let Some(next) = lines.get(i).and_then(|line| strip_quote(line)) {
+                quoted.push(next);
+                i += 1;
+            }
+            blocks.push(Block::Quote(parse_blocks(&quoted, depth + 1)));
+            continue;
+        }
+
+        if let Some((ordered, _)) = list_marker(line).filter(|_| can_nest) {
+            let (list, used) = parse_list(&lines[i..], ordered, depth);
+            blocks.push(list);
+            i += used;
+            continue;
+        }
+
+        let start = i;
+        i += 1;
+        while i < lines.len() && !interrupts_paragraph(lines[i], depth) {
+            i += 1;
+        }
+        let text: Vec<&str> = lines[start..i].iter().map(|line| line.trim()).collect();
+        blocks.push(Block::Paragraph(parse_inline(&text.join("\n"))));
+    }
+    blocks
+}
+
+/// Items at the same indent as the first, returns the list and how many lines it took
+fn parse_list(lines: &[&str], ordered: bool, depth: usize) -> (Block, usize) {
+    let base = indent(lines[0]);
+    let mut items: Vec<Vec<Block>> = vec![];
+    let mut i = 0;
+    while i < lines.len() {
+        let Some((item_ordered, content_start)) = list_marker(lines[i]) else {
+            break;
+        };
+        if item_ordered != ordered || indent(lines[i]) != base {
+            break;
+        }
+        let mut item_lines = vec![&lines[i][content_start..]];
+        i += 1;
+        while i < lines.len() {
+            let line = lines[i];
+            if line.trim().is_empty() {
+                // a blank line only stays in the list if the list carries on after it
+                let carries_on = lines.get(i + 1).is_some_and(|next| {
+                    indent(next) > base || list_marker(next).is_some_and(|(o, _)| o == ordered)
+                });
+                if !carries_on {
+                    break;
+                }
+                item_lines.push("");
+            } else if indent(line) > base {
+                // nested content, indented under the item
+                item_lines.push(dedent(line, content_start));
+            } else if list_marker(line).is_some() || interrupts_paragraph(line, depth) {
+                break;
+            } else {
+                // lazy continuation of the item's text
+                item_lines.push(line.trim());
+            }
+            i += 1;
+        }
+        items.push(parse_blocks(&item_lines, depth + 1));
+    }
+    (Block::List { ordered, items }, i)
+}
+
+fn indent(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+/// Strips up to `width` leading whitespace characters, which can be more than one byte each
+fn dedent(line: &str, width: usize) -> &str {
+    let mut rest = line;
+    for _ in 0..width {
+        match rest.strip_prefix(char::is_whitespace) {
+            Some(stripped) => rest = stripped,
+            None => break,
+        }
+    }
+    rest
+}
+
+/// `- item`, `* item`, `+ item`, `1. item` or `1) item`, gives (ordered, where the text starts)
+fn list_marker(line: &str) -> Option<(bool, usize)> {
+    let indent = indent(line);
+    let rest = &line[indent..];
+    if (rest.starts_with("- ") || rest.starts_with("* ") || rest.starts_with("+ "))
+        && !rest[2..].trim().is_empty()
+    {
+        return Some((false, indent + 2));
+    }
+    let digits = rest.chars().take_while(|c| c.is_ascii_digit()).count();
+    let after = &rest[digits..];
+    if (1..=9).contains(&digits)
+        && (after.starts_with(". ") || after.starts_with(") "))
+        && !after[2..].trim().is_empty()
+    {
+        return Some((true, indent + digits + 2));
+    }
+    None
+}
+
+/// The text after `>`, if the line is quoted
+fn strip_quote(line: &str) -> Option<&str> {
+    let rest = line.trim_start().strip_prefix('>')?;
+    Some(rest.strip_prefix(' ').unwrap_or(rest))
+}
+
+/// The fence (```` ``` ```` or `~~~`, maybe longer) and the info string after it
+fn opening_fence(line: &str) -> Option<(&str, &str)> {
+    let rest = line.trim_start();
+    let fence_char = rest.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let len = rest.chars().take_while(|c| *c == fence_char).count();
+    let (fence, info) = rest.split_at(len);
+    // backticks in the info string would make it inline code instead
+    (len >= 3 && !(fence_char == '`' && info.contains('`'))).then_some((fence, info.trim()))
+}
+
+fn closes_fence(line: &str, fence: &str) -> bool {
+    let rest = line.trim();
+    let fence_char = fence.chars().next().unwrap_or('`');
+    rest.len() >= fence.len() && rest.chars().all(|c| c == fence_char)
+}
+
+/// Quotes and lists only start a new block if they'd be allowed to nest at `depth`
+fn interrupts_paragraph(line: &str, depth: usize) -> bool {
+    let can_nest = depth < MAX_NESTING;
+    line.trim().is_empty()
+        || opening_fence(line).is_some()
+        || (can_nest && (strip_quote(line).is_some() || list_marker(line).is_some()))
+}
+
+pub fn parse_inline(text: &str) -> Vec<Inline> {
+    Inlines::new(text, 0).parse()
+}
+
+/// Inline parsing of one run of text, emphasis and link text are runs of their own a level deeper.
+/// Brackets, parentheses and backtick runs are matched up front and delimiters remember how
+/// their last search went, so a message full of openers that never close isn't rescanned.
+struct Inlines<'a> {
+    text: &'a str,
+    depth: usize,
+    /// Starts of the backtick runs, by run length
+    backtick_runs: HashMap<usize, Vec<usize>>,
+    /// The matching `]` for each `[`, outside code spans and escapes
+    brackets: HashMap<usize, usize>,
+    /// The matching `)` for each `(`
+    parens: HashMap<usize, usize>,
+    /// Each delimiter's last search for a closing
+    closings: HashMap<&'a str, Closing>,
+}
+
+/// Where a search for a closing delimiter started and what it found. Searching again from
+/// anywhere it passed through finds the same thing, which is anywhere in `from..found`
+/// except inside the strong runs it skipped.
+struct Closing {
+    from: usize,
+    found: Option<usize>,
+    skipped: Vec<(usize, usize)>,
+}
+
+impl Closing {
+    fn covers(&self, from: usize) -> bool {
+        let skipped = self.skipped.partition_point(|(start, _)| *start < from);
+        from >= self.from
+            && self.found.is_none_or(|found| from < found)
+            && (skipped == 0 || self.skipped[skipped - 1].1 <= from)
+    }
+}
+
+impl<'a> Inlines<'a> {
+    fn new(text: &'a str, depth: usize) -> Self {
+        let mut backtick_runs: HashMap<usize, Vec<usize>> = HashMap::new();
+        let mut parens = HashMap::new();
+        let mut open_parens = vec![];
+        let mut run_start = None;
+        for (i, c) in text.char_indices() {
+            match (c, run_start) {
+                ('`', None) => run_start = Some(i),
+                ('`', Some(_)) => {}
+                (_, Some(start)) => {
+                    backtick_runs.entry(i - start).or_default().push(start);
+                    run_start = None;
+                }
+                _ => {}
+            }
+            match c {
+                '(' => open_parens.push(i),
+                ')' => {
+                    if let Some(open) = open_parens.pop() {
+                        parens.insert(open, i);
+                    }
+                }
+                _ => {}
+            }
+        }
+        if let Some(start) = run_start {
+            backtick_runs
+                .entry(text.len() - start)
+                .or_default()
+                .push(start);
+        }
+
+        let mut inlines = Self {
+            text,
+            depth,
+            backtick_runs,
+            brackets: HashMap::new(),
+            parens,
+            closings: HashMap::new(),
+        };
+        let mut open_brackets = vec![];
+        let mut i = 0;
+        while let Some(c) = text[i..].chars().next() {
+            match c {
+                '\\' | '`' => {
+                    i = inlines.skip(i);
+                    continue;
+                }
+                '[' => open_brackets.push(i),
+                ']' => {
+                    if let Some(open) = open_brackets.pop() {
+                        inlines.brackets.insert(open, i);
+                    }
+                }
+                _ => {}
+            }
+            i += c.len_utf8();
+        }
+        inlines
+    }
+
+    fn parse(&mut self) -> Vec<Inline> {
+        let text = self.text;
+        // emphasis and links past this are left as text
+        let can_nest = self.depth < MAX_NESTING;
+        let mut inlines = vec![];
+        let mut plain = String::new();
+        let mut prev: Option<char> = None;
+        let mut at = 0;
+
+        while let Some(c) = text[at..].chars().next() {
+            let rest = &text[at..];
+            let mut parsed: Option<(Vec<Inline>, usize)> = None;
+            match c {
+                '\\' => {
+                    // escaped punctuation is just the character
+                    if let Some(escaped) =
+                        rest[1..].chars().next().filter(char::is_ascii_punctuation)
+                    {
+                        plain.push(escaped);
+                        prev = Some(escaped);
+                        at += 1 + escaped.len_utf8();
+                        continue;
+                    }
+                }
+                '\n' => parsed = Some((vec![Inline::LineBreak], 1)),
+                '`' => {
+                    if let Some((code, len)) = self.code_span(at) {
+                        parsed = Some((vec![Inline::Code(code)], len));
+                    } else {
+                        // an unmatched run of backticks is literal, all of it
+                        let len = backtick_run(rest);
+                        plain.push_str(&rest[..len]);
+                        prev = Some('`');
+                        at += len;
+                        continue;
+                    }
+                }
+                '*' | '_' if can_nest => {
+                    parsed = self
+                        .emphasis(at, prev)
+                        .map(|(inline, len)| (vec![inline], len))
+                }
+                '[' if can_nest => parsed = self.link(at),
+                _ => {}
+            }
+
+            match parsed {
+                Some((parsed, len)) => {
+                    if !plain.is_empty() {
+                        inlines.push(Inline::Text(std::mem::take(&mut plain)));
+                    }
+                    inlines.extend(parsed);
+                    prev = rest[..len].chars().last();
+                    at += len;
+                }
+                None => {
+                    plain.push(c);
+                    prev = Some(c);
+                    at += c.len_utf8();
+                }
+            }
+        }
+        if !plain.is_empty() {
+            inlines.push(Inline::Text(plain));
+        }
+        inlines
+    }
+
+    /// Past an escape, or a code span or the backtick run that would have opened one
+    fn skip(&self, at: usize) -> usize {
+        let rest = &self.text[at..];
+        if let Some(escaped) = rest.strip_prefix('\\') {
+            return at + 1 + escaped.chars().next().map_or(0, char::len_utf8);
+        }
+        match self.code_span(at) {
+            Some((_, len)) => at + len,
+            None => at + backtick_run(rest),
+        }
+    }
+
+    /// `` `code` `` at `at`, gives the code and how many bytes the span took.
+    /// It closes at the next run of exactly as many backticks.
+    fn code_span(&self, at: usize) -> Option<(String, usize)> {
+        let ticks = backtick_run(&self.text[at..]);
+        let runs = self.backtick_runs.get(&ticks)?;
+        let next = runs.partition_point(|start| *start < at + ticks);
+        let close = *runs.get(next)?;
+        let code = &self.text[at + ticks..close];
+        // one space of padding on each side is dropped, so `` `ticks` `` works
+        let code = if code.len() >= 2
+            && code.starts_with(' ')
+            && code.ends_with(' ')
+            && !code.trim().is_empty()
+        {
+            &code[1..code.len() - 1]
+        } else {
+            code
+        };
+        Some((code.replace('\n', " "), close + ticks - at))
+    }
+
+    /// `*em*`, `_em_`, `**strong**` or `__strong__`
+    fn emphasis(&mut self, at: usize, prev: Option<char>) -> Option<(Inline, usize)> {
+        let text = self.text;
+        let delim_char = text[at..].chars().next()?;
+        // snake_case_names aren't emphasis
+        if delim_char == '_' && prev.is_some_and(char::is_alphanumeric) {
+            return None;
+        }
+        let strong = text[at + 1..].starts_with(delim_char);
+        let delim = &text[at..at + if strong { 2 } else { 1 }];
+        let after = at + delim.len();
+        if text[after..].is_empty() || text[after..].starts_with(char::is_whitespace) {
+            return None;
+        }
+
+        let close = self.find_closing(after, delim)?;
+        let followed_by = text[close + delim.len()..].chars().next();
+        if delim_char == '_' && followed_by.is_some_and(char::is_alphanumeric) {
+            return None;
+        }
+        let children = Inlines::new(&text[after..close], self.depth + 1).parse();
+        let inline = if strong {
+            Inline::Strong(children)
+        } else {
+            Inline::Emphasis(children)
+        };
+        Some((inline, close + delim.len() - at))
+    }
+
+    /// Where `delim` closes after `from`, skipping escapes, code spans and nested strong runs
+    fn find_closing(&mut self, from: usize, delim: &'a str) -> Option<usize> {
+        if let Some(closing) = self.closings.get(delim).filter(|c| c.covers(from)) {
+            return closing.found;
+        }
+        let mut closing = Closing {
+            from,
+            found: None,
+            skipped: vec![],
+        };
+        closing.found = self.search_closing(&mut closing, delim);
+        let found = closing.found;
+        self.closings.insert(delim, closing);
+        found
+    }
+
+    fn search_closing(&mut self, closing: &mut Closing, delim: &'a str) -> Option<usize> {
+        let text = self.text;
+        let delim_char = delim.chars().next()?;
+        let from = closing.from;
+        let mut i = from;
+        while let Some(c) = text[i..].chars().next() {
+            let rest = &text[i..];
+            if c == '\\' || c == '`' {
+                i = self.skip(i);
+                continue;
+            }
+            if delim.len() == 1 && c == delim_char && rest[1..].starts_with(delim_char) {
+                // `*a **b** c*`, the inner strong isn't our closing
+                match self.find_closing(i + 2, &rest[..2]) {
+                    Some(close) => {
+                        closing.skipped.push((i, close + 2));
+                        i = close + 2;
+                    }
+                    None => i += 2,
+                }
+                continue;
+            }
+            if i > from
+                && rest.starts_with(delim)
+                && text[..i].chars().last().is_some_and(|c| !c.is_whitespace())
+            {
+                return Some(i);
+            }
+            i += c.len_utf8();
+        }
+        None
+    }
+
+    /// `[text](url)` at `at`, unsafe urls leave just the text
+    fn link(&mut self, at: usize) -> Option<(Vec<Inline>, usize)> {
+        let text = self.text;
+        let label_end = *self.brackets.get(&at)?;
+        // parentheses in the url have to balance, like CommonMark
+        let open = label_end + 1;
+        if !text[open..].starts_with('(') {
+            return None;
+        }
+        let close = *self.parens.get(&open)?;
+        // anything after the url is a title, which chat has no use for
+        let url = text[open + 1..close]
+            .split_whitespace()
+            .next()
+            .unwrap_or("");
+        let len = close + 1 - at;
+
+        let children = Inlines::new(&text[at + 1..label_end], self.depth + 1).parse();
+        if is_safe_url(url) {
+            let url = url.to_string();
+            Some((vec![Inline::Link { url, children }], len))
+        } else {
+            Some((children, len))
+        }
+    }
+}
+
+/// How many backticks `text` starts with
+fn backtick_run(text: &str) -> usize {
+    text.chars().take_while(|c| *c == '`').count()
+}
+
+/// `javascript:` and friends never make it into an href
+fn is_safe_url(url: &str) -> bool {
+    let lower = url.to_lowercase();
+    ["https://", "http://", "mailto:"]
+        .iter()
+        .any(|scheme| lower.starts_with(scheme) && lower.len() > scheme.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str) -> Inline {
+        Inline::Text(text.to_string())
+    }
+
+    fn paragraph(text: &str) -> Block {
+        Block::Paragraph(parse_inline(text))
+    }
+
+    #[test]
+    fn inline_styles() {
+        assert_eq!(
+            parse_inline("a **b** *c* `d`"),
+            vec![
+                text("a "),
+                Inline::Strong(vec![text("b")]),
+                text(" "),
+                Inline::Emphasis(vec![text("c")]),
+                text(" "),
+                Inline::Code("d".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn snake_case_is_not_emphasis() {
+        assert_eq!(
+            parse_inline("snake_case_name"),
+            vec![text("snake_case_name")]
+        );
+    }
+
+    #[test]
+    fn escapes_and_unmatched_backticks_are_literal() {
+        assert_eq!(parse_inline(r"\*a\* ``b"), vec![text("*a* ``b")]);
+    }
+
+    #[test]
+    fn code_spans_keep_their_contents() {
+        assert_eq!(
+            parse_inline("`` `ticks` `` and `*no*`"),
+            vec![
+                Inline::Code("`ticks`".to_string()),
+                text(" and "),
+                Inline::Code("*no*".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn links() {
+        assert_eq!(
+            parse_inline("[docs](https://example.com/a_(b) \"title\")"),
+            vec![Inline::Link {
+                url: "https://example.com/a_(b)".to_string(),
+                children: vec![text("docs")],
+            }]
+        );
+    }
+
+    #[test]
+    fn unsafe_links_keep_only_their_text() {
+        assert_eq!(
+            parse_inline("[click](javascript:alert(1))"),
+            vec![text("click")]
+        );
+    }
+
+    #[test]
+    fn line_breaks_are_kept() {
+        assert_eq!(
+            parse_inline("a\nb"),
+            vec![text("a"), Inline::LineBreak, text("b")]
+        );
+    }
+
+    #[test]
+    fn fenced_code() {
+        assert_eq!(
+            parse("```Rust\nlet a = *b*;\n```\nafter"),
+            vec![
+                Block::Code {
+                    lang: Some("rust".to_string()),
+                    code: "let a = *b*;".to_string(),
+                },
+                paragraph("after"),
+            ]
+        );
+    }
+
+    #[test]
+    fn unclosed_fence_runs_to_the_end() {
+        assert_eq!(
+            parse("~~~\ncode\nmore"),
+            vec![Block::Code {
+                lang: None,
+                code: "code\nmore".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn quotes() {
+        assert_eq!(
+            parse("> a\n> > b\nc"),
+            vec![
+                Block::Quote(vec![paragraph("a"), Block::Quote(vec![paragraph("b")])]),
+                paragraph("c"),
+            ]
+        );
+    }
+
+    #[test]
+    fn lists() {
+        assert_eq!(
+            parse("1. a\n   - b\n2. c"),
+            vec![Block::List {
+                ordered: true,
+                items: vec![
+                    vec![
+                        paragraph("a"),
+                        Block::List {
+                            ordered: false,
+                            items: vec![vec![paragraph("b")]],
+                        },
+                    ],
+                    vec![paragraph("c")],
+                ],
+            }]
+        );
+    }
+
+    #[test]
+    fn multibyte_indents_in_lists() {
+        for message in [
+            "1. a\n\u{a0}\u{a0}b",
+            "- a\n\u{3000}b",
+            "- a\n\u{3000}\u{3000}\u{3000}b",
+        ] {
+            let blocks = parse(message);
+            assert!(matches!(blocks.as_slice(), [Block::List { items, .. }] if items.len() == 1));
+        }
+    }
+
+    #[test]
+    fn deep_nesting_is_left_as_text() {
+        let message = ">".repeat(200_000);
+        let mut blocks = parse(&message);
+        let mut depth = 0;
+        while let [Block::Quote(inner)] = blocks.as_slice() {
+            blocks = inner.clone();
+            depth += 1;
+        }
+        assert_eq!(depth, MAX_NESTING);
+        assert_eq!(blocks, vec![paragraph(&">".repeat(200_000 - MAX_NESTING))]);
+
+        let message: String = (0..200)
+            .map(|i| format!("{}- a\n", " ".repeat(i * 2)))
+            .collect();
+        assert!(!parse(&message).is_empty());
+    }
+
+    #[test]
+    fn deep_inline_nesting_is_left_as_text() {
+        let message = format!("{}a{}", "[".repeat(30_000), "](http://a)".repeat(30_000));
+        let mut inlines = parse_inline(&message);
+        let mut depth = 0;
+        while let [Inline::Link { children, .. }] = inlines.as_slice() {
+            inlines = children.clone();
+            depth += 1;
+        }
+        assert_eq!(depth, MAX_NESTING);
+        let left = 30_000 - MAX_NESTING;
+        assert_eq!(
+            inlines,
+            vec![text(&format!(
+                "{}a{}",
+                "[".repeat(left),
+                "](http://a)".repeat(left)
+            ))]
+        );
+    }
+
+    #[test]
+    fn unclosed_delimiters_are_not_rescanned() {
+        // every opener used to search the rest of the message for its closing
+        let message = "**a ".repeat(100_000);
+        assert_eq!(parse_inline(&message), vec![text(&message)]);
+
+        let message = "*a **b** ".repeat(50_000);
+        let strong = parse_inline(&message)
+            .iter()
+            .filter(|inline| matches!(inline, Inline::Strong(_)))
+            .count();
+        assert_eq!(strong, 50_000);
+
+        for message in ["[a".repeat(100_000), "[a](b ".repeat(100_000)] {
+            assert_eq!(parse_inline(&message), vec![text(&message)]);
+        }
+    }
+}
@@ -0,0 +1,488 @@
+use crate::util::{timezone_offset, MS_PER_DAY};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use turtle_protocol::{SendableId, UserId};
+
+/// Lowercased words in a message, anything that isn't alphanumeric splits words
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect()
+}
+
+/// Splits text into (segment, is_match) pairs, a word matches if it starts with any of the terms
+pub fn highlight(text: &str, terms: &[String]) -> Vec<(String, bool)> {
+    let mut segments: Vec<(String, bool)> = vec![];
+    let mut rest = text;
+    while let Some(first) = rest.chars().next() {
+        // alternate between runs of word characters and everything else
+        let is_word = first.is_alphanumeric();
+        let end = rest
+            .find(|c: char| c.is_alphanumeric() != is_word)
+            .unwrap_or(rest.len());
+        let (run, tail) = rest.split_at(end);
+        let lower = run.to_lowercase();
+        let is_match = is_word && terms.iter().any(|term| lower.starts_with(term.as_str()));
+        match segments.last_mut() {
+            Some((last, last_match)) if *last_match == is_match => last.push_str(run),
+            _ => segments.push((run.to_string(), is_match)),
+        }
+        rest = tail;
+    }
+    segments
+}
+
+/// Parses `YYYY-MM-DD` into milliseconds since the epoch, at local midnight like the times we show
+pub fn parse_date(date: &str) -> Option<f64> {
+    let utc_midnight = parse_utc_date(date)?;
+    Some(utc_midnight + timezone_offset(utc_midnight))
+}
+
+/// Parses `YYYY-MM-DD` into milliseconds since the epoch, at midnight UTC
+fn parse_utc_date(date: &str) -> Option<f64> {
+    let mut parts = date.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: i64 = parts.next()?.parse().ok()?;
+    let day: i64 = parts.next()?.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // days from civil, see http://howardhinnant.github.io/date_algorithms.html
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = (if y >= 0 { y } else { y - 399 }) / 400;
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+    Some(days as f64 * MS_PER_DAY)
+}
+
+/// A parsed search query like `deploy from:alice in:#ops after:2024-01-01`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Query {
+    pub terms: Vec<String>,
+    pub from: Option<String>,
+    pub in_mailbox: Option<String>,
+    pub after: Option<f64>,
+    pub before: Option<f64>,
+}
+
+impl Query {
+    pub fn parse(query: &str) -> Self {
+        let mut parsed = Query::default();
+        for word in query.split_whitespace() {
+            match word.split_once(':') {
+                Some(("from", name)) if !name.is_empty() => {
+                    parsed.from = Some(name.trim_start_matches('@').to_lowercase());
+                }
+                Some(("in", name)) if !name.is_empty() => {
+                    parsed.in_mailbox = Some(name.to_lowercase());
+                }
+                Some(("after", date)) if parse_date(date).is_some() => {
+                    parsed.after = parse_date(date);
+                }
+                // before is inclusive of the whole day
+                Some(("before", date)) if parse_date(date).is_some() => {
+                    parsed.before = parse_date(date).map(|ts| ts + MS_PER_DAY);
+                }
+                _ => parsed.terms.extend(tokenize(word)),
+            }
+        }
+        parsed
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+            && self.from.is_none()
+            && self.in_mailbox.is_none()
+            && self.after.is_none()
+            && self.before.is_none()
+    }
+}
+
+/// What the index keeps about each message, enough to filter and to show it as a result.
+/// It outlives the message being in memory, so results don't vanish when a timeline is trimmed,
+/// until the index is trimmed too.
+#[derive(Clone, Debug)]
+pub struct IndexedMessage {
+    pub mailbox: SendableId,
+    pub from: UserId,
+    pub ts: f64,
+    pub content: String,
+    tokens: Vec<String>,
+}
+
+/// Inverted index from words to message ids, kept sorted so prefixes are a range scan
+#[derive(Clone, Debug, Default)]
+pub struct SearchIndex {
+    postings: BTreeMap<String, HashSet<String>>,
+    messages: HashMap<String, IndexedMessage>,
+    /// Each mailbox's message ids oldest first, for trimming
+    by_mailbox: HashMap<SendableId, BTreeSet<(i64, String)>>,
+}
+
+/// Orders messages by when they were sent, ms is fine enough
+fn age_key(ts: f64, message_id: &str) -> (i64, String) {
+    (ts as i64, message_id.to_string())
+}
+
+impl SearchIndex {
+    pub fn add(
+        &mut self,
+        message_id: &str,
+        mailbox: SendableId,
+        from: UserId,
+        ts: f64,
+        content: &str,
+    ) {
+        // re-adding replaces, that's how edits work
+        self.remove(message_id);
+        let mut tokens = tokenize(content);
+        tokens.sort();
+        tokens.dedup();
+        for token in &tokens {
+            self.postings
+                .entry(token.clone())
+                .or_default()
+                .insert(message_id.to_string());
+        }
+        self.by_mailbox
+            .entry(mailbox)
+            .or_default()
+            .insert(age_key(ts, message_id));
+        self.messages.insert(
+            message_id.to_string(),
+            IndexedMessage {
+                mailbox,
+                from,
+                ts,
+                content: content.to_string(),
+                tokens,
+            },
+        );
+    }
+
+    /// Re-indexes a message with new content, if it's indexed at all
+    pub fn edit(&mut self, message_id: &str, content: &str) {
+        if let Some(indexed) = self.messages.get(message_id) {
+            let (mailbox, from, ts) = (indexed.mailbox, indexed.from, indexed.ts);
+            self.add(message_id, mailbox, from, ts, content);
+        }
+    }
+
+    pub fn get(&self, message_id: &str) -> Option<&IndexedMessage> {
+        self.messages.get(message_id)
+    }
+
+    pub fn remove(&mut self, message_id: &str) {
+        if let Some(indexed) = self.messages.remove(message_id) {
+            if let Some(ids) = self.by_mailbox.get_mut(&indexed.mailbox) {
+                ids.remove(&age_key(indexed.ts, message_id));
+                if ids.is_empty() {
+                    self.by_mailbox.remove(&indexed.mailbox);
+                }
+            }
+            for token in indexed.tokens {
+                if let Some(ids) = self.postings.get_mut(&token) {
+                    ids.remove(message_id);
+                    if ids.is_empty() {
+                        self.postings.remove(&token);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Forgets everything from one mailbox, for when it's gone
+    pub fn remove_mailbox(&mut self, mailbox: SendableId) {
+        self.trim_mailbox(mailbox, 0);
+    }
+
+    /// Forgets a mailbox's oldest messages past `capacity`
+    pub fn trim_mailbox(&mut self, mailbox: SendableId, capacity: usize) {
+        let oldest: Vec<String> = match self.by_mailbox.get(&mailbox) {
+            Some(ids) if ids.len() > capacity => ids
+                .iter()
+                .take(ids.len() - capacity)
+                .map(|(_, id)| id.clone())
+                .collect(),
+            _ => return,
+        };
+        for id in oldest {
+            self.remove(&id);
+        }
+    }
+
+    /// Trims every mailbox, for when the capacity changes
+    pub fn trim(&mut self, capacity: usize) {
+        let mailboxes: Vec<SendableId> = self.by_mailbox.keys().copied().collect();
+        for mailbox in mailboxes {
+            self.trim_mailbox(mailbox, capacity);
+        }
+    }
+
+    fn prefix_matches(&self, prefix: &str) -> HashSet<String> {
+        self.postings
+            .range(prefix.to_string()..)
+            .take_while(|(token, _)| token.starts_with(prefix))
+            .flat_map(|(_, ids)| ids.iter().cloned())
+            .collect()
+    }
+
+    /// Message ids matching every term and filter, newest first.
+    /// `from` and `in_mailbox` are already resolved by the caller.
+    /// Nothing from `ignored` users comes back, whatever the query.
+    pub fn search(
+        &self,
+        query: &Query,
+        from: Option<&[UserId]>,
+        in_mailbox: Option<&[SendableId]>,
+        ignored: &HashSet<UserId>,
+    ) -> Vec<(String, IndexedMessage)> {
+        let mut candidates: Option<HashSet<String>> = None;
+        for term in &query.terms {
+            let matches = self.prefix_matches(term);
+            candidates = Some(match candidates {
+                Some(so_far) => so_far.intersection(&matches).cloned().collect(),
+                None => matches,
+            });
+        }
+
+        let passes_filters = |indexed: &IndexedMessage| {
+            !ignored.contains(&indexed.from)
+                && from.map(|ids| ids.contains(&indexed.from)).unwrap_or(true)
+                && in_mailbox
+                    .map(|ids| ids.contains(&indexed.mailbox))
+                    .unwrap_or(true)
+                && query.after.map(|after| indexed.ts >= after).unwrap_or(true)
+                && query
+                    .before
+                    .map(|before| indexed.ts < before)
+                    .unwrap_or(true)
+        };
+
+        let mut results: Vec<_> = match candidates {
+            Some(ids) => ids
+                .into_iter()
+                .filter_map(|id| self.messages.get(&id).map(|indexed| (id, indexed.clone())))
+                .filter(|(_, indexed)| passes_filters(indexed))
+                .collect(),
+            // filters only, e.g. `from:alice`
+            None => self
+                .messages
+                .iter()
+                .filter(|(_, indexed)| passes_filters(indexed))
+                .map(|(id, indexed)| (id.clone(), indexed.clone()))
+                .collect(),
+        };
+        results.sort_by(|a, b| b.1.ts.total_cmp(&a.1.ts));
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use turtle_protocol::ChannelId;
+
+    const GENERAL: SendableId = SendableId::C(ChannelId(1));
+    const RANDOM: SendableId = SendableId::C(ChannelId(2));
+    const ALICE: UserId = UserId(1);
+    const BOB: UserId = UserId(2);
+
+    fn ids(results: Vec<(String, IndexedMessage)>) -> Vec<String> {
+        results.into_iter().map(|(id, _)| id).collect()
+    }
+
+    fn index() -> SearchIndex {
+        let mut index = SearchIndex::default();
+        index.add("1", GENERAL, ALICE, 1000.0, "Deploying the new build");
+        index.add("2", GENERAL, BOB, 2000.0, "deploy failed, rolling back");
+        index.add("3", RANDOM, ALICE, 3000.0, "lunch?");
+        index
+    }
+
+    #[test]
+    fn tokenize_splits_on_anything_but_letters_and_digits() {
+        assert_eq!(
+            tokenize("Hello, wörld! v2.0 snake_case"),
+            vec!["hello", "wörld", "v2", "0", "snake", "case"]
+        );
+        assert!(tokenize(" ,.!? ").is_empty());
+    }
+
+    #[test]
+    fn parse_date_is_midnight() {
+        assert_eq!(parse_date("1970-01-01"), Some(0.0));
+        assert_eq!(parse_date("1970-01-02"), Some(MS_PER_DAY));
+        assert_eq!(parse_date("2000-03-01"), Some(11_017.0 * MS_PER_DAY));
+        assert_eq!(parse_date("1969-12-31"), Some(-MS_PER_DAY));
+    }
+
+    #[test]
+    fn parse_date_rejects_nonsense() {
+        for date in [
+            "",
+            "2024",
+            "2024-13-01",
+            "2024-01-32",
+            "2024-00-10",
+            "yesterday",
+        ] {
+            assert_eq!(parse_date(date), None, "{date}");
+        }
+    }
+
+    #[test]
+    fn query_filters() {
+        let query =
+            Query::parse("Deploy from:@Alice in:#ops after:1970-01-02 before:1970-01-02 to:x");
+        assert_eq!(query.terms, vec!["deploy", "to", "x"]);
+        assert_eq!(query.from.as_deref(), Some("alice"));
+        assert_eq!(query.in_mailbox.as_deref(), Some("#ops"));
+        assert_eq!(query.after, Some(MS_PER_DAY));
+        // before covers the whole day
+        assert_eq!(query.before, Some(2.0 * MS_PER_DAY));
+        assert!(Query::parse("  ").is_empty());
+    }
+
+    #[test]
+    fn terms_match_word_prefixes_newest_first() {
+        let index = index();
+        assert_eq!(
+            ids(index.search(&Query::parse("deploy"), None, None, &HashSet::new())),
+            ["2", "1"]
+        );
+        assert_eq!(
+            ids(index.search(&Query::parse("deploy back"), None, None, &HashSet::new())),
+            ["2"]
+        );
+        assert!(index
+            .search(&Query::parse("ploy"), None, None, &HashSet::new())
+            .is_empty());
+    }
+
+    #[test]
+    fn filters_work_without_terms() {
+        let index = index();
+        let query = Query::parse("from:alice");
+        assert_eq!(
+            ids(index.search(&query, Some(&[ALICE]), None, &HashSet::new())),
+            ["3", "1"]
+        );
+        assert_eq!(
+            ids(index.search(&query, Some(&[ALICE]), Some(&[GENERAL]), &HashSet::new())),
+            ["1"]
+        );
+        let query = Query {
+            after: Some(1500.0),
+            before: Some(3000.0),
+            ..Query::default()
+        };
+        assert_eq!(
+            ids(index.search(&query, None, None, &HashSet::new())),
+            ["2"]
+        );
+    }
+
+    #[test]
+    fn edits_replace_and_removals_forget() {
+        let mut index = index();
+        index.edit("1", "never mind");
+        index.edit("4", "not indexed");
+        assert!(index.get("4").is_none());
+        let edited = index.get("1").unwrap();
+        assert_eq!(
+            (edited.mailbox, edited.from, edited.ts),
+            (GENERAL, ALICE, 1000.0)
+        );
+        assert_eq!(edited.content, "never mind");
+        assert_eq!(
+            ids(index.search(&Query::parse("deploy"), None, None, &HashSet::new())),
+            ["2"]
+        );
+        assert_eq!(
+            ids(index.search(&Query::parse("mind"), None, None, &HashSet::new())),
+            ["1"]
+        );
+        index.remove("2");
+        assert!(index
+            .search(&Query::parse("deploy"), None, None, &HashSet::new())
+            .is_empty());
+        assert!(index
+            .postings
+            .keys()
+            .all(|token| !token.starts_with("deploy")));
+    }
+
+    #[test]
+    fn removing_a_mailbox_forgets_its_messages() {
+        let mut index = index();
+        index.remove_mailbox(GENERAL);
+        assert_eq!(
+            ids(index.search(
+                &Query::parse("from:alice"),
+                Some(&[ALICE]),
+                None,
+                &HashSet::new()
+            )),
+            ["3"]
+        );
+        assert!(index
+            .search(&Query::parse("deploy"), None, None, &HashSet::new())
+            .is_empty());
+        assert!(index.get("1").is_none());
+        assert!(!index.by_mailbox.contains_key(&GENERAL));
+    }
+
+    #[test]
+    fn trimming_keeps_the_index_bounded() {
+        let mut index = SearchIndex::default();
+        for n in 0..10_000 {
+            // a page of older history now and then
+            let ts = if n % 10 == 0 { -(n as f64) } else { n as f64 };
+            index.add(
+                &n.to_string(),
+                GENERAL,
+                ALICE,
+                ts,
+                &format!("word{n} shared"),
+            );
+            index.trim_mailbox(GENERAL, 100);
+        }
+        index.add("other", RANDOM, BOB, 0.0, "shared");
+        assert_eq!(index.messages.len(), 101);
+        assert_eq!(index.by_mailbox[&GENERAL].len(), 100);
+        // a word for each message left, and "shared"
+        assert_eq!(index.postings.len(), 101);
+        let found = index.search(
+            &Query::parse("shared"),
+            None,
+            Some(&[GENERAL]),
+            &HashSet::new(),
+        );
+        assert_eq!(found.len(), 100);
+        // the newest are what's left
+        assert_eq!(found[0].0, "9999");
+        assert!(found.iter().all(|(_, indexed)| indexed.ts >= 9_889.0));
+
+        index.trim(10);
+        assert_eq!(index.messages.len(), 11);
+    }
+
+    #[test]
+    fn ignored_users_never_show_up() {
+        let index = index();
+        let ignored = HashSet::from([ALICE]);
+        assert_eq!(
+            ids(index.search(&Query::parse("deploy"), None, None, &ignored)),
+            ["2"]
+        );
+        // not even when asked for by name
+        assert!(index
+            .search(&Query::parse("from:alice"), Some(&[ALICE]), None, &ignored)
+            .is_empty());
+    }
+}
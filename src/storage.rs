@@ -0,0 +1,171 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use turtle_protocol::{ChannelId, SendableId, UserId};
+use wasm_bindgen::prelude::wasm_bindgen;
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_name = localStorage)]
+    pub type LocalStorage;
+
+    #[wasm_bindgen(static_method_of = LocalStorage, js_class = "localStorage", js_name = getItem)]
+    pub fn get_item(key: String) -> Option<String>;
+
+    #[wasm_bindgen(static_method_of = LocalStorage, js_class = "localStorage", js_name = setItem)]
+    pub fn set_item(key: String, value: String);
+
+    #[wasm_bindgen(static_method_of = LocalStorage, js_class = "localStorage", js_name = removeItem)]
+    pub fn remove_item(key: String);
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum NotifyLevel {
+    #[default]
+    All,
+    Mentions,
+    Muted,
+}
+
+impl NotifyLevel {
+    pub const ALL: [NotifyLevel; 3] = [NotifyLevel::All, NotifyLevel::Mentions, NotifyLevel::Muted];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            NotifyLevel::All => "All messages",
+            NotifyLevel::Mentions => "Mentions only",
+            NotifyLevel::Muted => "Muted",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum SortOrder {
+    #[default]
+    Alphabetical,
+    Recent,
+    UnreadFirst,
+    Manual,
+}
+
+impl SortOrder {
+    pub const ALL: [SortOrder; 4] = [
+        SortOrder::Alphabetical,
+        SortOrder::Recent,
+        SortOrder::UnreadFirst,
+        SortOrder::Manual,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            SortOrder::Alphabetical => "A-Z",
+            SortOrder::Recent => "Recent",
+            SortOrder::UnreadFirst => "Unread first",
+            SortOrder::Manual => "Manual",
+        }
+    }
+}
+
+/// Sidebar lists that can be dragged into order, each keeps its own order
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SidebarSection {
+    Favorites,
+    Channels,
+    Groups,
+    Users,
+}
+
+/// Per-account settings that only live in this browser
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Preferences {
+    #[serde(with = "pairs")]
+    pub notify: HashMap<SendableId, NotifyLevel>,
+    /// (mailbox, message id), oldest first
+    pub bookmarks: Vec<(SendableId, String)>,
+    pub starred: HashSet<SendableId>,
+    pub sort_order: SortOrder,
+    /// Drag-and-drop order for `SortOrder::Manual`
+    pub manual_orders: HashMap<SidebarSection, Vec<SendableId>>,
+    /// Messages kept per mailbox, `None` for the default
+    pub mailbox_capacity: Option<usize>,
+    /// Users whose messages are collapsed and never count as unread
+    pub ignored: HashSet<UserId>,
+    /// Private aliases for users, shown instead of their username
+    #[serde(with = "pairs")]
+    pub nicknames: HashMap<UserId, String>,
+    /// Categories picked for channels here, they win over the server's
+    #[serde(with = "pairs")]
+    pub categories: HashMap<ChannelId, String>,
+    /// Sidebar categories that are folded away, by name
+    pub collapsed: HashSet<String>,
+    /// Show messages exactly as typed instead of formatting their markdown
+    pub plain_text: bool,
+    /// Pop up desktop notifications, the browser asks for permission when it's turned on
+    pub desktop_notifications: bool,
+}
+
+impl Preferences {
+    fn key(user_id: UserId) -> String {
+        format!("preferences-{}", user_id.0)
+    }
+
+    pub fn load(user_id: UserId) -> Self {
+        LocalStorage::get_item(Self::key(user_id))
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, user_id: UserId) {
+        if let Ok(json) = serde_json::to_string(self) {
+            LocalStorage::set_item(Self::key(user_id), json);
+        }
+    }
+}
+
+/// Unsent messages per mailbox, kept apart from preferences since they change on every keystroke
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Drafts(#[serde(with = "pairs")] pub HashMap<SendableId, String>);
+
+impl Drafts {
+    fn key(user_id: UserId) -> String {
+        format!("drafts-{}", user_id.0)
+    }
+
+    pub fn load(user_id: UserId) -> Self {
+        LocalStorage::get_item(Self::key(user_id))
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, user_id: UserId) {
+        if let Ok(json) = serde_json::to_string(self) {
+            LocalStorage::set_item(Self::key(user_id), json);
+        }
+    }
+}
+
+/// JSON object keys have to be strings, so maps keyed by ids are stored as lists of pairs
+mod pairs {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::HashMap;
+    use std::hash::Hash;
+
+    pub fn serialize<K, V, S>(map: &HashMap<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        K: Serialize,
+        V: Serialize,
+        S: Serializer,
+    {
+        serializer.collect_seq(map.iter())
+    }
+
+    pub fn deserialize<'de, K, V, D>(deserializer: D) -> Result<HashMap<K, V>, D::Error>
+    where
+        K: Deserialize<'de> + Eq + Hash,
+        V: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        let pairs: Vec<(K, V)> = Vec::deserialize(deserializer)?;
+        Ok(pairs.into_iter().collect())
+    }
+}
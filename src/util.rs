@@ -0,0 +1,30 @@
+//! Small helpers shared by modules that otherwise have nothing to do with each other.
+
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen::prelude::wasm_bindgen;
+
+pub const MS_PER_DAY: f64 = 86_400_000.0;
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+extern "C" {
+    type Date;
+
+    #[wasm_bindgen(constructor)]
+    fn new(ts: f64) -> Date;
+
+    #[wasm_bindgen(method, js_name = getTimezoneOffset)]
+    fn get_timezone_offset(this: &Date) -> f64;
+}
+
+/// How far local time is behind UTC at `ts`, in milliseconds, negative east of Greenwich
+#[cfg(target_arch = "wasm32")]
+pub fn timezone_offset(ts: f64) -> f64 {
+    Date::new(ts).get_timezone_offset() * 60_000.0
+}
+
+/// Tests run natively, without a browser to ask, so they run in UTC
+#[cfg(not(target_arch = "wasm32"))]
+pub fn timezone_offset(_ts: f64) -> f64 {
+    0.0
+}
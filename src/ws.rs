@@ -1,4 +1,4 @@
-use leptos::logging;
+use leptos::{batch, logging};
 use std::cell::RefCell;
 use std::collections::HashMap;
 use web_sys::{ErrorEvent, MessageEvent, WebSocket};
@@ -219,9 +219,12 @@ impl Runtime {
         match maybe_fs {
             Some(fs) => {
                 logging::log!("handling msg of type {t} with {} handlers", fs.len());
-                for f in fs {
-                    f(msg.clone())
-                }
+                // one message, one round of updates for whatever it touched
+                batch(|| {
+                    for f in fs {
+                        f(msg.clone())
+                    }
+                });
             }
             None => {
                 logging::error!("No handlers registered for {t} messages");